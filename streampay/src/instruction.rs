@@ -1,25 +1,420 @@
-use solana_program::program_error::ProgramError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program,
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
 use std::convert::TryInto;
+use crate::constants::{MAX_BATCH_STREAMS, MAX_TRANSACTION_SIZE};
+use crate::payment_stream::{BlackoutWindow, CancellationPolicy, ReleaseCurve, SplitRecipient};
+use crate::state::ConfigParams;
+use crate::utils::{find_config_address, find_treasury_address, find_vault_address};
+
+/// One stream created by `BatchInitializeStreams`. Batched streams take the
+/// defaults for every setting beyond the schedule: no delegate, splits or
+/// blackout windows, a payer-only cancellation policy and no transfers.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub struct StreamEntry {
+    pub recipient: Pubkey,
+    pub start_time: u64,
+    pub interval: u64,
+    pub amount_per_interval: u64,
+    pub total_amount: u64,
+    pub cliff_time: u64,
+    pub cliff_amount: u64,
+    pub end_time: u64,
+    pub release_curve: ReleaseCurve,
+}
 
 /// Enum that defines the instructions supported by the program.
-#[derive(Debug, PartialEq)]
+///
+/// Permission matrix. Every role is checked by matching the signer's key against
+/// the payment stream or the program config and requiring its signature:
+///
+/// | Instruction              | Payer | Recipient | Delegate | Config admin | Fee authority | Anyone |
+/// |--------------------------|-------|-----------|----------|--------------|---------------|--------|
+/// | `InitializeStream`       | yes   |           |          |              |               |        |
+/// | `BatchInitializeStreams` | yes   |           |          |              |               |        |
+/// | `UpdateStream`           | yes   |           | yes      |              |               |        |
+/// | `RequireUpdateConsent`   | yes   | yes       |          |              |               |        |
+/// | `UpdateBlackoutWindows`  | yes   |           | yes      |              |               |        |
+/// | `TerminateStream`        | yes   | yes       |          |              |               |        |
+/// | `TransferRecipient`      | yes   | yes       |          |              |               |        |
+/// | `TopUp`                  |       |           |          |              |               | yes    |
+/// | `Withdraw`               |       | yes       |          |              |               |        |
+/// | `WithdrawMax`            |       | yes       |          |              |               |        |
+/// | `CrankWithdraw`          |       |           |          |              |               | yes    |
+/// | `PauseStream`            | yes   |           | yes      |              |               |        |
+/// | `ResumeStream`           | yes   |           | yes      |              |               |        |
+/// | `QueryStream`            |       |           |          |              |               | yes    |
+/// | `InitializeConfig`       |       |           |          | yes          |               |        |
+/// | `CollectFees`            |       |           |          |              | yes           |        |
+/// | `QueryTreasury`          |       |           |          |              |               | yes    |
+/// | `UpdateConfig`           |       |           |          | yes          |               |        |
+/// | `TransferAdmin`          |       |           |          | yes          |               |        |
+/// | `SetProgramPaused`       |       |           |          | yes          |               |        |
+/// | `MigrateStream`          |       |           |          |              |               | yes    |
+/// | `SetPayoutDestination`   |       | yes       |          |              |               |        |
+///
+/// Whether the payer, the recipient, both or neither may terminate a stream is
+/// set by its cancellation policy, and whether they may reassign its recipient
+/// by its transfer flags. Once a stream requires the recipient's consent to
+/// updates, `UpdateStream` and `UpdateBlackoutWindows` also need the
+/// recipient's signature, or that of every recipient of a split stream.
+/// `InitializeConfig` must be signed by the program's upgrade authority, which
+/// becomes the config admin, and `TransferAdmin` also needs the new admin's
+/// signature.
+#[derive(Clone, Debug, PartialEq)]
 pub enum StreamPayInstruction {
+    /// Creates a payment stream and escrows the deposit in the stream's vault.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Payer
+    /// 1. `[writable]` Payment stream account, zeroed and `PaymentStream::LEN` bytes long
+    /// 2. `[]` Recipient, unused by split streams
+    /// 3. `[writable]` Vault PDA
+    /// 4. `[]` Program config PDA
+    /// 5. `[writable]` Treasury PDA
+    /// 6. `[]` System program
+    ///
+    /// SPL token streams name their `mint` and additionally pass:
+    /// 7. `[]` Token mint
+    /// 8. `[writable]` Vault associated token account
+    /// 9. `[]` Token program
+    /// 10. `[writable]` Payer token account
+    /// 11. `[]` Associated token account program
+    /// 12. `[writable]` Treasury associated token account
+    ///
+    /// `release_curve` decides how `total_amount` is released: stepped streams
+    /// release `amount_per_interval` every `interval` seconds, linear and
+    /// exponential-decay streams release nothing before `cliff_time`, unlock
+    /// `cliff_amount` at the cliff and the rest until `end_time`, and breakpoint
+    /// streams follow their own table. The optional `delegate` may pause, resume
+    /// and update the stream on the payer's behalf. `cancellation_policy` decides
+    /// who may terminate the stream, and whoever terminates forfeits
+    /// `cancellation_penalty_bps` of its share to the other party: a payer of the
+    /// unvested funds, a recipient of the vested funds. The transfer flags
+    /// decide who may later reassign the recipient with `TransferRecipient`, and
+    /// `update_requires_recipient_consent` makes `UpdateStream` need the
+    /// recipient's signature. `allow_crank` lets anyone trigger withdrawals to
+    /// the recipient with `CrankWithdraw`. Nothing vests during `blackout_windows`. A non-empty
+    /// `splits` list pays the stream out to several recipients by weight, each
+    /// withdrawing its own share.
     InitializeStream {
         start_time: u64,
         interval: u64,
         amount_per_interval: u64,
+        total_amount: u64,
+        cliff_time: u64,
+        cliff_amount: u64,
+        end_time: u64,
+        release_curve: ReleaseCurve,
+        delegate: Option<Pubkey>,
+        cancellation_policy: CancellationPolicy,
+        cancellation_penalty_bps: u16,
+        transferable_by_recipient: bool,
+        transferable_by_sender: bool,
+        update_requires_recipient_consent: bool,
+        allow_crank: bool,
+        blackout_windows: Vec<BlackoutWindow>,
+        splits: Vec<SplitRecipient>,
+        mint: Option<Pubkey>, // `None` for lamport streams
     },
+    /// Changes the interval and amount of a stepped payment stream from now on.
+    /// Whatever vested under the old terms stays withdrawable, including the
+    /// interval in progress, which is settled pro rata at the old rate.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Payer or delegate
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Program config PDA
+    /// 3. `[signer]` Recipient, when the stream requires its consent to updates,
+    ///    or each recipient of a split stream in split order
     UpdateStream {
         interval: u64,
         amount_per_interval: u64,
     },
+    /// Terminates a payment stream, paying vested funds to the recipient and
+    /// refunding the rest of the escrow to the payer, less the cancellation
+    /// penalty owed by whoever terminates. Either the payer or the recipient signs, as the stream's
+    /// cancellation policy allows. Every recipient of a split stream is paid its
+    /// vested share, and only the payer may terminate a split stream.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer?, writable]` Payer
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[signer?, writable]` Recipient
+    /// 3. `[writable]` Vault PDA
+    /// 4. `[]` Program config PDA
+    /// 5. `[writable]` Treasury PDA
+    /// 6. `[]` System program
+    ///
+    /// Lamport streams then pass, once the recipient sets a payout destination:
+    /// 7. `[writable]` Payout destination, which is paid instead of the recipient
+    ///
+    /// SPL token streams additionally pass:
+    /// 7. `[writable]` Token mint, which collects transfer fees withheld in the vault
+    /// 8. `[writable]` Vault associated token account
+    /// 9. `[]` Token program
+    /// 10. `[writable]` Recipient token account, owned by the payout destination once the recipient sets one,
+    ///     omitted by split streams
+    /// 11. `[writable]` Payer token account
+    /// 12. `[writable]` Treasury associated token account
+    ///
+    /// Split streams then pass, for each split recipient in order:
+    /// - `[writable]` The recipient's wallet, or its token account for SPL token streams
     TerminateStream,
+    /// Withdraws vested funds from the stream's vault to the recipient, or to a
+    /// destination the recipient names. Once the recipient sets a payout
+    /// destination, the funds can only go there. Recipients of a split stream
+    /// withdraw up to their own share.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Recipient
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[writable]` Vault PDA
+    /// 3. `[]` Program config PDA
+    /// 4. `[writable]` Treasury PDA
+    /// 5. `[]` System program
+    ///
+    /// Lamport streams may additionally pass, and must once the recipient sets a payout destination:
+    /// 6. `[writable]` Destination, instead of the recipient
+    ///
+    /// SPL token streams additionally pass:
+    /// 6. `[]` Token mint
+    /// 7. `[writable]` Vault associated token account
+    /// 8. `[]` Token program
+    /// 9. `[writable]` Destination token account, owned by the payout destination once the recipient sets one
+    /// 10. `[writable]` Treasury associated token account
     Withdraw {
         amount: u64,
     },
+    /// Withdraws everything the recipient can withdraw right now, up to the
+    /// config's per-transaction limit, so callers need not compute the amount
+    /// first. Takes the same accounts as `Withdraw`.
+    WithdrawMax,
+    /// Lets anyone, such as a keeper bot, withdraw everything the recipient can
+    /// withdraw right now, up to the config's per-transaction limit, from a
+    /// stream that allows it, at most once every `MIN_CRANK_INTERVAL` seconds
+    /// after the recipient's last withdrawal, which is each split recipient's own
+    /// on a split stream. The payout always goes to the recipient, or its
+    /// payout destination, less the config's crank tip, which goes to the keeper.
+    ///
+    /// Accounts expected:
+    /// 0. `[writable]` Recipient, or one of the recipients of a split stream
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[writable]` Vault PDA
+    /// 3. `[]` Program config PDA
+    /// 4. `[writable]` Treasury PDA
+    /// 5. `[]` System program
+    ///
+    /// Lamport streams then pass:
+    /// 6. `[signer, writable]` Keeper
+    /// 7. `[writable]` Payout destination, when the recipient set one
+    ///
+    /// SPL token streams instead pass:
+    /// 6. `[]` Token mint
+    /// 7. `[writable]` Vault associated token account
+    /// 8. `[]` Token program
+    /// 9. `[writable]` Token account of the recipient, or of its payout destination
+    /// 10. `[writable]` Treasury associated token account
+    /// 11. `[signer]` Keeper
+    /// 12. `[writable]` Keeper token account
+    CrankWithdraw,
+    /// Pauses a payment stream. Nothing vests while it is paused, but funds that
+    /// vested before the pause stay withdrawable.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Payer or delegate
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Program config PDA
     PauseStream,
+    /// Resumes a paused payment stream, which picks up vesting where it left off.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Payer or delegate
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Program config PDA
     ResumeStream,
+    /// Sets the instruction's return data to a Borsh-serialized `StreamSnapshot`
+    /// of the stream's status and vested, withdrawable and remaining amounts at
+    /// the current time.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Payment stream account
     QueryStream,
+    /// Creates the program config PDA and funds the treasury PDA's rent-exempt
+    /// reserve. The signer, which must be the program's upgrade authority,
+    /// becomes the config admin.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Admin, the program's upgrade authority
+    /// 1. `[writable]` Program config PDA
+    /// 2. `[writable]` Treasury PDA
+    /// 3. `[]` System program
+    /// 4. `[]` The program's ProgramData account
+    InitializeConfig {
+        params: ConfigParams,
+    },
+    /// Sweeps the fees held by the treasury to a destination chosen by the fee authority.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Fee authority
+    /// 1. `[]` Program config PDA
+    /// 2. `[writable]` Treasury PDA
+    /// 3. `[writable]` Destination, a token account of the mint when collecting tokens
+    /// 4. `[]` System program
+    ///
+    /// Token fees additionally pass:
+    /// 5. `[]` Token mint
+    /// 6. `[writable]` Treasury associated token account
+    /// 7. `[]` Token program
+    CollectFees,
+    /// Sets the instruction's return data to the collectable treasury balance as a
+    /// little-endian u64.
+    ///
+    /// Accounts expected:
+    /// 0. `[]` Program config PDA
+    /// 1. `[]` Treasury PDA
+    ///
+    /// Token balances additionally pass:
+    /// 2. `[]` Token mint
+    /// 3. `[]` Treasury associated token account
+    QueryTreasury,
+    /// Replaces the config's limits and fee settings.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Admin
+    /// 1. `[writable]` Program config PDA
+    UpdateConfig {
+        params: ConfigParams,
+    },
+    /// Hands the admin role to a new key, which must co-sign.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Admin
+    /// 1. `[writable]` Program config PDA
+    /// 2. `[signer]` New admin
+    TransferAdmin,
+    /// Freezes or unfreezes every state-changing instruction at once. While
+    /// paused, `allow_vested_withdrawals` still lets recipients withdraw what
+    /// has already vested.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Admin
+    /// 1. `[writable]` Program config PDA
+    SetProgramPaused {
+        is_paused: bool,
+        allow_vested_withdrawals: bool,
+    },
+    /// Reassigns the recipient of a payment stream. The new recipient inherits
+    /// everything vested but not yet withdrawn, along with all future payouts.
+    /// The current recipient signs when the stream is `transferable_by_recipient`,
+    /// the payer when it is `transferable_by_sender`.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Current recipient or payer
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` New recipient
+    /// 3. `[]` Program config PDA
+    /// 4. `[]` Recipient being replaced, when the payer transfers a split stream
+    TransferRecipient,
+    /// Adds `amount` to a stream's escrow and raises its `total_amount`. The end
+    /// time moves out so the stream keeps releasing at its current rate, and a
+    /// stream that ran dry resumes accruing from now. Any funder may sign; only
+    /// stepped and linear streams can be topped up, by at least the config's
+    /// minimum deposit.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Funder
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[writable]` Vault PDA
+    /// 3. `[]` Program config PDA
+    /// 4. `[writable]` Treasury PDA
+    /// 5. `[]` System program
+    ///
+    /// SPL token streams additionally pass:
+    /// 6. `[]` Token mint
+    /// 7. `[writable]` Vault associated token account
+    /// 8. `[]` Token program
+    /// 9. `[writable]` Funder token account
+    /// 10. `[]` Associated token account program
+    /// 11. `[writable]` Treasury associated token account
+    TopUp {
+        amount: u64,
+    },
+    /// Makes every later `UpdateStream` need the recipient's signature, or the
+    /// signatures of all the recipients of a split stream. Either party may turn
+    /// this on, and it cannot be turned off.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Payer or recipient
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Program config PDA
+    RequireUpdateConsent,
+    /// Replaces the blackout windows of a stream that have not begun yet. Nothing
+    /// vests inside a window, and no transaction is needed at either end of it.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Payer or delegate
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Program config PDA
+    /// 3. `[signer]` Recipient, when the stream requires its consent to updates,
+    ///    or each recipient of a split stream in split order
+    UpdateBlackoutWindows {
+        blackout_windows: Vec<BlackoutWindow>,
+    },
+    /// Creates up to `MAX_BATCH_STREAMS` payment streams funded by one payer, such
+    /// as a payroll run. The program creates each stream account, which signs the
+    /// transaction, and the whole batch fails if any stream does.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Payer
+    /// 1. `[]` Program config PDA
+    /// 2. `[writable]` Treasury PDA
+    /// 3. `[]` System program
+    ///
+    /// Then, for each stream in order:
+    /// - `[signer, writable]` New payment stream account
+    /// - `[writable]` Vault PDA
+    ///
+    /// SPL token batches name their `mint` and additionally pass:
+    /// - `[]` Token mint
+    /// - `[]` Token program
+    /// - `[writable]` Payer token account
+    /// - `[]` Associated token account program
+    /// - `[writable]` Treasury associated token account
+    /// - `[writable]` Vault associated token account of each stream, in order
+    BatchInitializeStreams {
+        streams: Vec<StreamEntry>,
+        mint: Option<Pubkey>, // `None` for lamport streams
+    },
+    /// Rewrites a payment stream stored under an earlier account layout in the
+    /// current one. New fields are taken out of the account's reserved space; only
+    /// a layout that outgrows it grows the account to `PaymentStream::LEN`, with
+    /// the payer covering the extra rent. Streams already on the current layout are
+    /// left as they are, and the terms of a migrated stream never change.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer, writable]` Payer of any extra rent
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` System program
+    MigrateStream,
+    /// Sets where the recipient's withdrawals are paid, which is the only place
+    /// `Withdraw`, `WithdrawMax` and `CrankWithdraw` pay it to afterwards. Naming
+    /// the recipient itself clears it, and so does transferring the stream to a
+    /// new recipient. Recipients of a split stream are always paid at their own
+    /// address.
+    ///
+    /// Accounts expected:
+    /// 0. `[signer]` Recipient
+    /// 1. `[writable]` Payment stream account
+    /// 2. `[]` Payout destination, the owner of the destination token account for SPL token streams
+    /// 3. `[]` Program config PDA
+    SetPayoutDestination,
 }
 
 impl StreamPayInstruction {
@@ -33,10 +428,52 @@ impl StreamPayInstruction {
                 let start_time = u64::from_le_bytes(data[1..9].try_into().unwrap());
                 let interval = u64::from_le_bytes(data[9..17].try_into().unwrap());
                 let amount_per_interval = u64::from_le_bytes(data[17..25].try_into().unwrap());
+                let total_amount = u64::from_le_bytes(data[25..33].try_into().unwrap());
+                let cliff_time = u64::from_le_bytes(data[33..41].try_into().unwrap());
+                let cliff_amount = u64::from_le_bytes(data[41..49].try_into().unwrap());
+                let end_time = u64::from_le_bytes(data[49..57].try_into().unwrap());
+                let rest = &mut &data[57..];
+                let release_curve = ReleaseCurve::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let delegate = Option::<Pubkey>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let cancellation_policy = CancellationPolicy::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let cancellation_penalty_bps = u16::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let transferable_by_recipient = bool::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let transferable_by_sender = bool::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let update_requires_recipient_consent = bool::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let allow_crank = bool::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let blackout_windows = Vec::<BlackoutWindow>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let splits = Vec::<SplitRecipient>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let mint = Option::<Pubkey>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
                 Ok(StreamPayInstruction::InitializeStream {
                     start_time,
                     interval,
                     amount_per_interval,
+                    total_amount,
+                    cliff_time,
+                    cliff_amount,
+                    end_time,
+                    release_curve,
+                    delegate,
+                    cancellation_policy,
+                    cancellation_penalty_bps,
+                    transferable_by_recipient,
+                    transferable_by_sender,
+                    update_requires_recipient_consent,
+                    allow_crank,
+                    blackout_windows,
+                    splits,
+                    mint,
                 })
             }
             1 => {
@@ -57,6 +494,56 @@ impl StreamPayInstruction {
             4 => Ok(StreamPayInstruction::PauseStream),
             5 => Ok(StreamPayInstruction::ResumeStream),
             6 => Ok(StreamPayInstruction::QueryStream),
+            7 => {
+                // InitializeConfig instruction
+                let params = ConfigParams::try_from_slice(&data[1..])
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                Ok(StreamPayInstruction::InitializeConfig { params })
+            }
+            8 => Ok(StreamPayInstruction::CollectFees),
+            9 => Ok(StreamPayInstruction::QueryTreasury),
+            10 => {
+                // UpdateConfig instruction
+                let params = ConfigParams::try_from_slice(&data[1..])
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                Ok(StreamPayInstruction::UpdateConfig { params })
+            }
+            11 => Ok(StreamPayInstruction::TransferAdmin),
+            12 => {
+                // SetProgramPaused instruction
+                let is_paused = data[1] != 0;
+                let allow_vested_withdrawals = data[2] != 0;
+                Ok(StreamPayInstruction::SetProgramPaused {
+                    is_paused,
+                    allow_vested_withdrawals,
+                })
+            }
+            13 => Ok(StreamPayInstruction::TransferRecipient),
+            14 => {
+                // TopUp instruction
+                let amount = u64::from_le_bytes(data[1..9].try_into().unwrap());
+                Ok(StreamPayInstruction::TopUp { amount })
+            }
+            15 => Ok(StreamPayInstruction::RequireUpdateConsent),
+            16 => {
+                // UpdateBlackoutWindows instruction
+                let blackout_windows = Vec::<BlackoutWindow>::try_from_slice(&data[1..])
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                Ok(StreamPayInstruction::UpdateBlackoutWindows { blackout_windows })
+            }
+            17 => {
+                // BatchInitializeStreams instruction
+                let rest = &mut &data[1..];
+                let streams = Vec::<StreamEntry>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                let mint = Option::<Pubkey>::deserialize(rest)
+                    .map_err(|_| ProgramError::InvalidInstructionData)?;
+                Ok(StreamPayInstruction::BatchInitializeStreams { streams, mint })
+            }
+            18 => Ok(StreamPayInstruction::WithdrawMax),
+            19 => Ok(StreamPayInstruction::CrankWithdraw),
+            20 => Ok(StreamPayInstruction::MigrateStream),
+            21 => Ok(StreamPayInstruction::SetPayoutDestination),
             _ => Err(ProgramError::InvalidInstructionData),
         }
     }
@@ -70,11 +557,41 @@ impl StreamPayInstruction {
                 start_time,
                 interval,
                 amount_per_interval,
+                total_amount,
+                cliff_time,
+                cliff_amount,
+                end_time,
+                release_curve,
+                delegate,
+                cancellation_policy,
+                cancellation_penalty_bps,
+                transferable_by_recipient,
+                transferable_by_sender,
+                update_requires_recipient_consent,
+                allow_crank,
+                blackout_windows,
+                splits,
+                mint,
             } => {
                 data[0] = 0; // Instruction code for InitializeStream
                 data[1..9].copy_from_slice(&start_time.to_le_bytes());
                 data.extend_from_slice(&interval.to_le_bytes());
                 data.extend_from_slice(&amount_per_interval.to_le_bytes());
+                data.extend_from_slice(&total_amount.to_le_bytes());
+                data.extend_from_slice(&cliff_time.to_le_bytes());
+                data.extend_from_slice(&cliff_amount.to_le_bytes());
+                data.extend_from_slice(&end_time.to_le_bytes());
+                data.extend_from_slice(&release_curve.try_to_vec().unwrap());
+                data.extend_from_slice(&delegate.try_to_vec().unwrap());
+                data.extend_from_slice(&cancellation_policy.try_to_vec().unwrap());
+                data.extend_from_slice(&cancellation_penalty_bps.to_le_bytes());
+                data.push(*transferable_by_recipient as u8);
+                data.push(*transferable_by_sender as u8);
+                data.push(*update_requires_recipient_consent as u8);
+                data.push(*allow_crank as u8);
+                data.extend_from_slice(&blackout_windows.try_to_vec().unwrap());
+                data.extend_from_slice(&splits.try_to_vec().unwrap());
+                data.extend_from_slice(&mint.try_to_vec().unwrap());
             }
             StreamPayInstruction::UpdateStream {
                 interval,
@@ -100,8 +617,160 @@ impl StreamPayInstruction {
             StreamPayInstruction::QueryStream => {
                 data[0] = 6; // Instruction code for QueryStream
             }
+            StreamPayInstruction::InitializeConfig { params } => {
+                data.truncate(1);
+                data[0] = 7; // Instruction code for InitializeConfig
+                data.extend_from_slice(&params.try_to_vec().unwrap());
+            }
+            StreamPayInstruction::CollectFees => {
+                data[0] = 8; // Instruction code for CollectFees
+            }
+            StreamPayInstruction::QueryTreasury => {
+                data[0] = 9; // Instruction code for QueryTreasury
+            }
+            StreamPayInstruction::UpdateConfig { params } => {
+                data.truncate(1);
+                data[0] = 10; // Instruction code for UpdateConfig
+                data.extend_from_slice(&params.try_to_vec().unwrap());
+            }
+            StreamPayInstruction::TransferAdmin => {
+                data[0] = 11; // Instruction code for TransferAdmin
+            }
+            StreamPayInstruction::SetProgramPaused {
+                is_paused,
+                allow_vested_withdrawals,
+            } => {
+                data[0] = 12; // Instruction code for SetProgramPaused
+                data[1] = *is_paused as u8;
+                data[2] = *allow_vested_withdrawals as u8;
+            }
+            StreamPayInstruction::TransferRecipient => {
+                data[0] = 13; // Instruction code for TransferRecipient
+            }
+            StreamPayInstruction::TopUp { amount } => {
+                data[0] = 14; // Instruction code for TopUp
+                data[1..9].copy_from_slice(&amount.to_le_bytes());
+            }
+            StreamPayInstruction::RequireUpdateConsent => {
+                data[0] = 15; // Instruction code for RequireUpdateConsent
+            }
+            StreamPayInstruction::UpdateBlackoutWindows { blackout_windows } => {
+                data.truncate(1);
+                data[0] = 16; // Instruction code for UpdateBlackoutWindows
+                data.extend_from_slice(&blackout_windows.try_to_vec().unwrap());
+            }
+            StreamPayInstruction::BatchInitializeStreams { streams, mint } => {
+                data.truncate(1);
+                data[0] = 17; // Instruction code for BatchInitializeStreams
+                data.extend_from_slice(&streams.try_to_vec().unwrap());
+                data.extend_from_slice(&mint.try_to_vec().unwrap());
+            }
+            StreamPayInstruction::WithdrawMax => {
+                data[0] = 18; // Instruction code for WithdrawMax
+            }
+            StreamPayInstruction::CrankWithdraw => {
+                data[0] = 19; // Instruction code for CrankWithdraw
+            }
+            StreamPayInstruction::MigrateStream => {
+                data[0] = 20; // Instruction code for MigrateStream
+            }
+            StreamPayInstruction::SetPayoutDestination => {
+                data[0] = 21; // Instruction code for SetPayoutDestination
+            }
         }
 
         data
     }
 }
+
+/// The token accounts funding a batch of SPL token streams.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchTokenFunding {
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+    pub payer_token_account: Pubkey,
+}
+
+/// Builds the `BatchInitializeStreams` instructions that create `streams`, each
+/// paired with the address of its new stream account. Streams are packed in
+/// order into as few instructions as possible, each holding at most
+/// `MAX_BATCH_STREAMS` streams and fitting in a transaction of its own signed
+/// by `payer` and its stream accounts. A stream too large for any transaction
+/// still gets an instruction of its own.
+pub fn batch_initialize_streams(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    streams: &[(Pubkey, StreamEntry)],
+    token_funding: Option<&BatchTokenFunding>,
+) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut batch_start = 0;
+    for batch_end in 1..=streams.len() {
+        let batch = &streams[batch_start..batch_end];
+        let fits = batch.len() <= MAX_BATCH_STREAMS
+            && transaction_size(payer, &batch_instruction(program_id, payer, batch, token_funding))
+                <= MAX_TRANSACTION_SIZE;
+        if !fits && batch.len() > 1 {
+            instructions.push(batch_instruction(program_id, payer, &batch[..batch.len() - 1], token_funding));
+            batch_start = batch_end - 1;
+        }
+    }
+    if batch_start < streams.len() {
+        instructions.push(batch_instruction(program_id, payer, &streams[batch_start..], token_funding));
+    }
+
+    instructions
+}
+
+/// Builds a single `BatchInitializeStreams` for `streams`.
+fn batch_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    streams: &[(Pubkey, StreamEntry)],
+    token_funding: Option<&BatchTokenFunding>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        AccountMeta::new(find_treasury_address(program_id).0, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+    let vaults: Vec<Pubkey> = streams
+        .iter()
+        .map(|(payment_stream, _)| find_vault_address(payment_stream, program_id).0)
+        .collect();
+    for ((payment_stream, _), vault) in streams.iter().zip(&vaults) {
+        accounts.push(AccountMeta::new(*payment_stream, true));
+        accounts.push(AccountMeta::new(*vault, false));
+    }
+
+    if let Some(funding) = token_funding {
+        let token_account = |owner: &Pubkey| {
+            get_associated_token_address_with_program_id(owner, &funding.mint, &funding.token_program)
+        };
+        accounts.push(AccountMeta::new_readonly(funding.mint, false));
+        accounts.push(AccountMeta::new_readonly(funding.token_program, false));
+        accounts.push(AccountMeta::new(funding.payer_token_account, false));
+        accounts.push(AccountMeta::new_readonly(spl_associated_token_account::id(), false));
+        accounts.push(AccountMeta::new(token_account(&find_treasury_address(program_id).0), false));
+        accounts.extend(vaults.iter().map(|vault| AccountMeta::new(token_account(vault), false)));
+    }
+
+    let streams = streams.iter().map(|(_, entry)| entry.clone()).collect();
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::BatchInitializeStreams {
+            streams,
+            mint: token_funding.map(|funding| funding.mint),
+        }
+        .pack(),
+        accounts,
+    )
+}
+
+/// The serialized size of a transaction holding only `instruction`, paid for by `payer`.
+fn transaction_size(payer: &Pubkey, instruction: &Instruction) -> usize {
+    let message = Message::new(std::slice::from_ref(instruction), Some(payer));
+    // A one-byte signature count, then a 64-byte signature for every signer
+    1 + usize::from(message.header.num_required_signatures) * 64 + message.serialize().len()
+}
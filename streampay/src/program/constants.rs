@@ -1,7 +1,14 @@
 pub type Lamports = u64;
-pub const MINIMUM_AMOUNT_LAMPORTS: Lamports = 1;
-pub const OPERATIONAL_FEE_RATE: f64 = 0.015; // 1.5%
+pub const MINIMUM_AMOUNT_LAMPORTS: Lamports = 1; // Floor for the configured minimum deposit
+pub const BPS_DENOMINATOR: u64 = 10_000; // Basis points in 100%
 
-pub const EXAMPLE_MINIMUM_AMOUNT: u64 = 100;  // Example minimum amount constant
-pub const EXAMPLE_OPERATIONAL_FEE_RATE: u64 = 2;  // Example operational fee rate constant
-pub const MAX_WITHDRAWAL_AMOUNT: u64 = 5000;  // Example maximum withdrawal amount constant
+pub const VAULT_SEED: &[u8] = b"vault"; // Seed prefix for the per-stream vault PDA
+pub const CONFIG_SEED: &[u8] = b"config"; // Seed for the program config PDA
+pub const TREASURY_SEED: &[u8] = b"treasury"; // Seed for the treasury PDA that receives fees
+pub const MAX_BREAKPOINTS: usize = 16; // Maximum points in a breakpoint release curve
+pub const MAX_BLACKOUT_WINDOWS: usize = 8; // Maximum blackout windows attached to a stream
+pub const MAX_SPLIT_RECIPIENTS: usize = 8; // Maximum recipients sharing a split stream
+pub const MAX_CRANK_TIP_BPS: u16 = 100; // Largest keeper tip on a cranked withdrawal, 1% in basis points
+pub const MIN_CRANK_INTERVAL: i64 = 86_400; // Seconds a keeper waits after a stream's last withdrawal to crank it
+pub const MAX_BATCH_STREAMS: usize = 8; // Maximum streams created by one `BatchInitializeStreams`, within its compute budget
+pub const MAX_TRANSACTION_SIZE: usize = 1232; // Largest serialized transaction the network accepts
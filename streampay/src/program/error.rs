@@ -21,6 +21,114 @@ pub enum PaymentError {
     #[error("Invalid start time")]
     InvalidStartTime,
 
+    #[error("Payment stream is already initialized")]
+    StreamAlreadyInitialized,
+
+    #[error("Vault account does not match the payment stream")]
+    InvalidVaultAccount,
+
+    #[error("Recipient does not match the payment stream")]
+    InvalidRecipient,
+
+    #[error("Payer does not match the payment stream")]
+    InvalidPayer,
+
+    #[error("Invalid token mint")]
+    InvalidMint,
+
+    #[error("Invalid token account")]
+    InvalidTokenAccount,
+
+    #[error("Token mint uses an unsupported extension")]
+    UnsupportedMintExtension,
+
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow,
+
+    #[error("Program config account is invalid or not initialized")]
+    InvalidConfigAccount,
+
+    #[error("Program config is already initialized")]
+    ConfigAlreadyInitialized,
+
+    #[error("Treasury account does not match the program config")]
+    InvalidTreasuryAccount,
+
+    #[error("Signer is not the fee authority")]
+    InvalidFeeAuthority,
+
+    #[error("Fee rate exceeds 100%")]
+    InvalidFeeRate,
+
+    #[error("Signer is not the config admin")]
+    InvalidAdmin,
+
+    #[error("Withdrawal exceeds the per-transaction limit")]
+    WithdrawalLimitExceeded,
+
+    #[error("Program is paused")]
+    ProgramPaused,
+
+    #[error("Payment stream is not paused")]
+    StreamNotPaused,
+
+    #[error("Signer is neither the payer nor the delegate of the payment stream")]
+    InvalidAuthority,
+
+    #[error("Cancellation policy does not allow this party to terminate the stream")]
+    TerminationNotAllowed,
+
+    #[error("Cancellation penalty exceeds 100%")]
+    InvalidPenaltyRate,
+
+    #[error("Payment stream does not allow this party to transfer it")]
+    TransferNotAllowed,
+
+    #[error("Split recipients must be distinct with weights adding up to 100%")]
+    InvalidSplits,
+
+    #[error("Batch holds no streams or more than one instruction can create")]
+    InvalidBatchSize,
+
+    #[error("Payment stream does not allow cranked withdrawals")]
+    CrankNotAllowed,
+
+    #[error("Crank tip exceeds the maximum rate")]
+    InvalidCrankTip,
+
+    #[error("Account is not a payment stream")]
+    InvalidAccountType,
+
+    #[error("Payment stream account layout version is not supported")]
+    UnsupportedAccountVersion,
+
+    #[error("Signer is not the program's upgrade authority")]
+    InvalidUpgradeAuthority,
+
+    #[error("Destination is not the payout destination the recipient set")]
+    InvalidPayoutDestination,
+
+    #[error("Split streams pay each recipient at its own address")]
+    PayoutDestinationNotSupported,
+
+    #[error("Payment stream was withdrawn from too recently to crank")]
+    CrankTooSoon,
+
+    #[error("Invalid time frame")]
+    InvalidTimeFrame,
+
+    #[error("Invalid release curve")]
+    InvalidReleaseCurve,
+
+    #[error("Invalid blackout windows")]
+    InvalidBlackoutWindows,
+
+    #[error("Payer, funder or stream authority did not sign")]
+    AuthorityNotSigner,
+
+    #[error("Recipient did not sign")]
+    RecipientNotSigner,
+
     // Add more custom error variants as needed
 }
 
@@ -41,6 +149,19 @@ pub enum StreamError {
     // Add more custom error variants as needed
 }
 
+/// Errors decoding a `StreamEvent` off-chain.
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventError {
+    #[error("Log data is not a stream event")]
+    NotAStreamEvent,
+
+    #[error("Unsupported stream event version {0}")]
+    UnsupportedVersion(u8),
+
+    #[error("Invalid stream event data")]
+    InvalidData,
+}
+
 impl From<PaymentError> for ProgramError {
     fn from(e: PaymentError) -> Self {
         ProgramError::Custom(e as u32)
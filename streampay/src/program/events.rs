@@ -0,0 +1,173 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{clock::UnixTimestamp, log::sol_log_data, pubkey::Pubkey};
+use crate::{
+    error::EventError,
+    payment_stream::{BlackoutWindow, PaymentStream},
+    state::ConfigParams,
+};
+
+/// Prefix of every event's log data, telling stream events apart from data
+/// logged by other programs.
+pub const EVENT_DISCRIMINATOR: [u8; 8] = *b"strmpay:";
+/// Layout version of the events, bumped whenever an existing variant changes.
+/// New variants are only ever appended, so their Borsh index stays stable.
+pub const EVENT_VERSION: u8 = 1;
+/// How the runtime prefixes data logged with `sol_log_data`.
+const PROGRAM_DATA_PREFIX: &str = "Program data: ";
+
+/// Structured record of a change to a payment stream, the treasury or the program config, emitted
+/// with `sol_log_data` for off-chain indexers. Each event is logged as the
+/// discriminator, the version byte and the Borsh-serialized event.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
+pub enum StreamEvent {
+    Created {
+        payment_stream: Pubkey,
+        payer: Pubkey,
+        recipient: Pubkey, // Default for split streams
+        mint: Option<Pubkey>, // `None` for lamport streams
+        total_amount: u64,
+        start_time: UnixTimestamp,
+    },
+    Withdrawn {
+        payment_stream: Pubkey,
+        recipient: Pubkey,
+        destination: Pubkey,
+        amount: u64, // Taken out of the stream, including the fee and tip
+        fee: u64,
+        keeper: Option<Pubkey>, // Set for cranked withdrawals
+        tip: u64,
+    },
+    ToppedUp {
+        payment_stream: Pubkey,
+        funder: Pubkey,
+        amount: u64,
+        total_amount: u64,
+        end_time: UnixTimestamp,
+    },
+    Paused {
+        payment_stream: Pubkey,
+        paused_at: UnixTimestamp,
+    },
+    Resumed {
+        payment_stream: Pubkey,
+        resumed_at: UnixTimestamp,
+        total_paused_duration: u64,
+    },
+    Updated {
+        payment_stream: Pubkey,
+        interval: u64,
+        amount_per_interval: u64,
+        end_time: UnixTimestamp,
+    },
+    Terminated {
+        payment_stream: Pubkey,
+        terminated_by: Pubkey,
+        recipient_amount: u64, // Paid to every recipient together, including the fee
+        refund_amount: u64,
+        fee: u64,
+    },
+    FeeCollected {
+        fee_authority: Pubkey,
+        destination: Pubkey,
+        mint: Option<Pubkey>, // `None` for lamport fees
+        amount: u64,
+    },
+    PayoutDestinationSet {
+        payment_stream: Pubkey,
+        recipient: Pubkey,
+        payout_destination: Option<Pubkey>, // `None` when the recipient is paid itself
+    },
+    RecipientTransferred {
+        payment_stream: Pubkey,
+        transferred_by: Pubkey, // The payer or the recipient being replaced
+        old_recipient: Pubkey,
+        new_recipient: Pubkey,
+    },
+    Migrated {
+        payment_stream: Pubkey,
+        from_version: u8,
+        to_version: u8,
+    },
+    ConfigUpdated {
+        admin: Pubkey,
+        params: ConfigParams, // The settings now in force, at initialization too
+    },
+    ProgramPauseSet {
+        admin: Pubkey,
+        is_paused: bool,
+        allow_vested_withdrawals: bool,
+    },
+    AdminTransferred {
+        old_admin: Pubkey,
+        new_admin: Pubkey,
+    },
+    BlackoutWindowsUpdated {
+        payment_stream: Pubkey,
+        blackout_windows: Vec<BlackoutWindow>, // Every window of the stream, including any already begun
+    },
+    UpdateConsentRequired {
+        payment_stream: Pubkey,
+        required_by: Pubkey,
+    },
+}
+
+impl StreamEvent {
+    /// The `Created` event of a newly opened stream.
+    pub fn created(payment_stream_key: &Pubkey, payment_stream: &PaymentStream) -> Self {
+        Self::Created {
+            payment_stream: *payment_stream_key,
+            payer: payment_stream.payer,
+            recipient: payment_stream.recipient,
+            mint: payment_stream.is_spl_token.then_some(payment_stream.mint),
+            total_amount: payment_stream.total_amount,
+            start_time: payment_stream.start_time,
+        }
+    }
+
+    /// Logs the event for indexers.
+    pub fn emit(&self) {
+        sol_log_data(&[&self.encode()]);
+    }
+
+    /// The discriminator, version byte and Borsh-serialized event.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = EVENT_DISCRIMINATOR.to_vec();
+        data.push(EVENT_VERSION);
+        data.extend_from_slice(&self.try_to_vec().unwrap());
+        data
+    }
+
+    /// Decodes event data as logged by `emit`.
+    pub fn decode(data: &[u8]) -> Result<Self, EventError> {
+        let payload = data
+            .strip_prefix(&EVENT_DISCRIMINATOR[..])
+            .ok_or(EventError::NotAStreamEvent)?;
+        match payload.split_first() {
+            Some((&EVENT_VERSION, event)) => Self::try_from_slice(event).map_err(|_| EventError::InvalidData),
+            Some((&version, _)) => Err(EventError::UnsupportedVersion(version)),
+            None => Err(EventError::InvalidData),
+        }
+    }
+
+    /// Decodes every stream event in a transaction's log messages, skipping
+    /// other log lines and data logged by other programs.
+    pub fn decode_logs<S: AsRef<str>>(logs: &[S]) -> Result<Vec<Self>, EventError> {
+        let mut events = Vec::new();
+        for log in logs {
+            let Some(fields) = log.as_ref().strip_prefix(PROGRAM_DATA_PREFIX) else {
+                continue;
+            };
+            for field in fields.split_whitespace() {
+                let data = STANDARD.decode(field).map_err(|_| EventError::InvalidData)?;
+                match Self::decode(&data) {
+                    Ok(event) => events.push(event),
+                    Err(EventError::NotAStreamEvent) => continue,
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
@@ -4,22 +4,164 @@ use solana_program::{
     program_error::ProgramError,
     pubkey::Pubkey,
 };
+use crate::constants::{BPS_DENOMINATOR, MAX_BLACKOUT_WINDOWS, MAX_BREAKPOINTS, MAX_SPLIT_RECIPIENTS};
+use crate::error::PaymentError;
+use crate::utils::calculate_fee;
+
+/// A point on a piecewise-linear release curve: `cumulative_amount` has vested by `timestamp`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub timestamp: UnixTimestamp,
+    pub cumulative_amount: u64,
+}
+
+/// A period from `start` up to `end` during which a stream does not vest, as
+/// if it had been paused and resumed at those times.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BlackoutWindow {
+    pub start: UnixTimestamp,
+    pub end: UnixTimestamp,
+}
+
+impl BlackoutWindow {
+    /// Seconds of the window that have passed by `current_time`.
+    fn elapsed(&self, current_time: UnixTimestamp) -> u64 {
+        current_time.min(self.end).saturating_sub(self.start).max(0) as u64
+    }
+}
+
+/// One of the recipients of a split stream, entitled to `weight_bps` of
+/// everything the stream releases.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq)]
+pub struct SplitRecipient {
+    pub recipient: Pubkey,
+    pub weight_bps: u16,
+    pub withdrawn_amount: u64, // Withdrawn by this recipient over the life of the stream
+    pub last_withdraw_time: UnixTimestamp, // When this recipient last withdrew, which paces cranks for it
+}
+
+impl SplitRecipient {
+    pub fn new(recipient: Pubkey, weight_bps: u16) -> Self {
+        SplitRecipient {
+            recipient,
+            weight_bps,
+            withdrawn_amount: 0,
+            last_withdraw_time: 0,
+        }
+    }
+
+    /// This recipient's share of `released_amount` that it has not withdrawn yet.
+    fn unwithdrawn_share(&self, released_amount: u64) -> u64 {
+        let share = released_amount as u128 * self.weight_bps as u128 / BPS_DENOMINATOR as u128;
+        (share as u64).saturating_sub(self.withdrawn_amount)
+    }
+}
+
+/// How the deposit of a stream is released over time.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq, Default)]
+pub enum ReleaseCurve {
+    /// `amount_per_interval` for every full `interval` since `start_time`.
+    #[default]
+    Stepped,
+    /// `cliff_amount` at `cliff_time`, then per-second linear until `end_time`.
+    Linear,
+    /// `cliff_amount` at `cliff_time`, then the unvested remainder halves every
+    /// `half_life` seconds. Whatever is left is released at `end_time`.
+    ExponentialDecay { half_life: u64 },
+    /// Linear interpolation between up to `MAX_BREAKPOINTS` points, starting from
+    /// nothing vested at `start_time`.
+    Breakpoints(Vec<Breakpoint>),
+}
+
+/// Who may terminate a stream, chosen at creation.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum CancellationPolicy {
+    #[default]
+    PayerOnly,
+    RecipientOnly,
+    Both,
+    /// The stream always runs to completion.
+    Neither,
+}
+
+/// One side of a payment stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamParty {
+    Payer,
+    Recipient,
+}
+
+impl CancellationPolicy {
+    pub fn allows(self, party: StreamParty) -> bool {
+        match self {
+            Self::PayerOnly => party == StreamParty::Payer,
+            Self::RecipientOnly => party == StreamParty::Recipient,
+            Self::Both => true,
+            Self::Neither => false,
+        }
+    }
+}
+
+/// Where a stream stands at a given time.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StreamStatus {
+    /// Nothing has vested yet because the stream has not started.
+    Scheduled,
+    Active,
+    Paused,
+    /// Everything has vested, though some of it may not be withdrawn yet.
+    Completed,
+    Terminated,
+}
+
+/// Compact view of a stream's value at a given time, returned by `QueryStream`
+/// so other programs can read it through a single CPI.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamSnapshot {
+    pub timestamp: UnixTimestamp,
+    pub status: StreamStatus,
+    pub vested_amount: u64, // Vested under the current terms, plus what earlier terms left unwithdrawn
+    pub withdrawable_amount: u64, // Vested and not withdrawn yet, by every recipient together
+    pub remaining_amount: u64, // Still to vest, nothing once the stream is terminated
+}
 
 #[derive(BorshSerialize, BorshDeserialize, Clone, Debug, PartialEq)]
 pub struct PaymentStream {
     pub payer: Pubkey,
-    pub recipient: Pubkey,
+    pub recipient: Pubkey, // Unused by split streams, which pay `splits` instead
+    pub delegate: Option<Pubkey>, // May pause, resume and update the stream on the payer's behalf
     pub start_time: UnixTimestamp,
     pub interval: u64,
     pub amount_per_interval: u64,
-    pub total_amount: u64,
-    pub withdrawn_amount: u64,
+    pub total_amount: u64, // Vesting under the current terms, including what has vested so far
+    pub withdrawn_amount: u64, // Withdrawn out of `total_amount`
+    pub accrued_unwithdrawn: u64, // Vested under earlier terms and not withdrawn yet
     pub last_withdraw_time: UnixTimestamp,
+    pub cliff_time: UnixTimestamp, // Nothing vests before the cliff
+    pub cliff_amount: u64, // Unlocked at the cliff, the rest vests linearly until `end_time`
+    pub end_time: UnixTimestamp,
+    pub release_curve: ReleaseCurve,
+    pub suspended_duration: u64, // Seconds the stream sat dry before a top-up, which did not accrue
+    pub cancellation_policy: CancellationPolicy,
+    pub cancellation_penalty_bps: u16, // Share of its own funds a terminating party forfeits to the other
+    pub transferable_by_recipient: bool, // Recipient may hand the stream over to a new wallet
+    pub transferable_by_sender: bool, // Payer may reassign the recipient, e.g. to correct a mistake
+    pub update_requires_recipient_consent: bool, // Recipient must co-sign changes to the terms
+    pub allow_crank: bool, // Anyone may trigger withdrawals to the recipient
     pub is_initialized: bool,
     pub is_terminated: bool,
     pub is_paused: bool,
-    pub custom_field: u64, // Add custom fields as needed
-    // Add more custom fields based on program requirements
+    pub paused_at: UnixTimestamp, // When the current pause started
+    pub total_paused_duration: u64, // Seconds spent paused before the current pause, which did not accrue
+    pub blackout_windows: Vec<BlackoutWindow>, // Scheduled periods that do not accrue, sorted by start
+    pub splits: Vec<SplitRecipient>, // Recipients sharing a split stream, empty for a single recipient
+    pub vault_bump: u8,
+    pub is_spl_token: bool,
+    pub mint: Pubkey, // Token mint for SPL token streams, unused for lamport streams
+    pub transfer_fees_withheld: u64, // Token-2022 transfer fees withheld from withdrawals
+    pub fees_paid: u64, // Operational fees charged on this stream, paid out to the treasury PDA
+    pub created_at: UnixTimestamp, // When the stream was opened, 0 for streams migrated from version 1
+    pub payout_destination: Option<Pubkey>, // Where the recipient has its withdrawals paid, `None` for itself
 }
 
 impl PaymentStream {
@@ -27,87 +169,841 @@ impl PaymentStream {
         PaymentStream {
             payer,
             recipient,
+            delegate: None,
             start_time: 0,
             interval: 0,
             amount_per_interval: 0,
             total_amount: 0,
             withdrawn_amount: 0,
+            accrued_unwithdrawn: 0,
             last_withdraw_time: 0,
+            cliff_time: 0,
+            cliff_amount: 0,
+            end_time: 0,
+            release_curve: ReleaseCurve::Stepped,
+            suspended_duration: 0,
+            cancellation_policy: CancellationPolicy::PayerOnly,
+            cancellation_penalty_bps: 0,
+            transferable_by_recipient: false,
+            transferable_by_sender: false,
+            update_requires_recipient_consent: false,
+            allow_crank: false,
             is_initialized: false,
             is_terminated: false,
             is_paused: false,
-            custom_field: 0, // Initialize custom fields
-            // Initialize more custom fields here
+            paused_at: 0,
+            total_paused_duration: 0,
+            blackout_windows: Vec::new(),
+            splits: Vec::new(),
+            vault_bump: 0,
+            is_spl_token: false,
+            mint: Pubkey::default(),
+            transfer_fees_withheld: 0,
+            fees_paid: 0,
+            created_at: 0,
+            payout_destination: None,
         }
     }
 
+    /// Tags payment stream accounts, telling them apart from the program's other accounts.
+    pub const DISCRIMINATOR: [u8; 8] = *b"pstream:";
+    /// Layout version of the account, bumped whenever the encoded fields change.
+    pub const VERSION: u8 = 4;
+    /// Bytes in front of the encoded stream: the discriminator and the version.
+    pub const HEADER_LEN: usize = 8 + 1;
+    /// Encoded size of a stream with the largest release curve, the most blackout
+    /// windows and the most split recipients.
+    pub const MAX_DATA_LEN: usize = 32 + 32 + (1 + 32) + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + 8
+        + (1 + 4 + MAX_BREAKPOINTS * 16) + 8 + 1 + 2 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 8 + 8
+        + (4 + MAX_BLACKOUT_WINDOWS * 16) + (4 + MAX_SPLIT_RECIPIENTS * (32 + 2 + 8 + 8)) + 1 + 1 + 32 + 8 + 8 + 8
+        + (1 + 32);
+    /// Zeroed space at the end of every account, left free for later use. Fields
+    /// added by later layout versions are taken out of it where they fit, so `LEN`
+    /// only grows for a layout that outgrows it.
+    pub const RESERVED_LEN: usize = 23;
+    /// Size of every payment stream account.
+    pub const LEN: usize = Self::HEADER_LEN + Self::MAX_DATA_LEN + Self::RESERVED_LEN;
+
+    /// Writes the discriminator, the version and the encoded stream, zeroing the
+    /// rest of the account.
     pub fn pack(&self, dst: &mut [u8]) -> Result<(), ProgramError> {
-        self.try_to_vec()
-            .map(|encoded| dst[..encoded.len()].copy_from_slice(&encoded))
-            .map_err(|_| ProgramError::InvalidAccountData)
+        let encoded = self.try_to_vec().map_err(|_| ProgramError::InvalidAccountData)?;
+        let dst = dst.get_mut(..Self::LEN).ok_or(ProgramError::AccountDataTooSmall)?;
+        let (header, data) = dst.split_at_mut(Self::HEADER_LEN);
+        header[..8].copy_from_slice(&Self::DISCRIMINATOR);
+        header[8] = Self::VERSION;
+        data.get_mut(..encoded.len())
+            .ok_or(ProgramError::AccountDataTooSmall)?
+            .copy_from_slice(&encoded);
+        data[encoded.len()..].fill(0);
+        Ok(())
     }
 
+    /// Reads a stream written by `pack`, rejecting other accounts and other
+    /// layout versions.
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        BorshDeserialize::try_from_slice(input).map_err(|_| ProgramError::InvalidAccountData)
+        if input.len() < Self::HEADER_LEN || input[..8] != Self::DISCRIMINATOR {
+            return Err(PaymentError::InvalidAccountType.into());
+        }
+        if input[8] != Self::VERSION {
+            return Err(PaymentError::UnsupportedAccountVersion.into());
+        }
+
+        // The encoded stream is followed by zeroed space, so ignore trailing bytes
+        Self::deserialize(&mut &input[Self::HEADER_LEN..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Reads a stream written under the current or any earlier layout version,
+    /// returning it with the version it was read from.
+    pub fn unpack_any_version(input: &[u8]) -> Result<(Self, u8), ProgramError> {
+        if !Self::is_stream_account(input) || input.len() < Self::HEADER_LEN {
+            return Err(PaymentError::InvalidAccountType.into());
+        }
+
+        let version = input[8];
+        let data = &mut &input[Self::HEADER_LEN..];
+        let payment_stream = match version {
+            1 => PaymentStreamV1::deserialize(data).map(Self::from),
+            // Version 2 had no `payout_destination`, whose `None` reads from the zeroed reserve
+            2 | 3 => PaymentStreamV3::deserialize(data).map(Self::from),
+            Self::VERSION => Self::deserialize(data),
+            _ => return Err(PaymentError::UnsupportedAccountVersion.into()),
+        }
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+
+        Ok((payment_stream, version))
+    }
+
+    /// Whether `input` holds a payment stream of any layout version.
+    pub fn is_stream_account(input: &[u8]) -> bool {
+        input.starts_with(&Self::DISCRIMINATOR)
+    }
+
+    /// Checks that the schedule is consistent with the release curve: stepped
+    /// streams need a non-zero interval and amount, cliff-based curves need
+    /// `start <= cliff <= end` and a cliff amount covered by the deposit, and
+    /// breakpoint tables must rise over time and end at the deposit.
+    pub fn validate_schedule(&self) -> Result<(), ProgramError> {
+        match &self.release_curve {
+            ReleaseCurve::Stepped => {
+                if self.interval == 0 || self.amount_per_interval == 0 {
+                    return Err(PaymentError::InvalidTimeFrame.into());
+                }
+            }
+            ReleaseCurve::Linear | ReleaseCurve::ExponentialDecay { .. } => {
+                if self.cliff_time < self.start_time
+                    || self.end_time < self.cliff_time
+                    || self.cliff_amount > self.total_amount
+                {
+                    return Err(PaymentError::InvalidTimeFrame.into());
+                }
+
+                if let ReleaseCurve::ExponentialDecay { half_life: 0 } = self.release_curve {
+                    return Err(PaymentError::InvalidReleaseCurve.into());
+                }
+            }
+            ReleaseCurve::Breakpoints(breakpoints) => {
+                let last = match breakpoints.last() {
+                    Some(last) if breakpoints.len() <= MAX_BREAKPOINTS => last,
+                    _ => return Err(PaymentError::InvalidReleaseCurve.into()),
+                };
+
+                let mut previous = Breakpoint {
+                    timestamp: self.start_time,
+                    cumulative_amount: 0,
+                };
+                for breakpoint in breakpoints {
+                    if breakpoint.timestamp <= previous.timestamp
+                        || breakpoint.cumulative_amount < previous.cumulative_amount
+                    {
+                        return Err(PaymentError::InvalidReleaseCurve.into());
+                    }
+                    previous = *breakpoint;
+                }
+
+                if last.cumulative_amount != self.total_amount {
+                    return Err(PaymentError::InvalidReleaseCurve.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the total amount vested at `current_time` according to the
+    /// release curve, including funds that were already withdrawn. The result
+    /// never exceeds `total_amount`.
+    pub fn calculate_vested_amount(&self, current_time: UnixTimestamp) -> Result<u64, ProgramError> {
+        let current_time = self.accrual_time(current_time);
+        let vested_amount = match &self.release_curve {
+            ReleaseCurve::Stepped => self.stepped_vested_amount(current_time)?,
+            ReleaseCurve::Linear => self.cliff_vested_amount(current_time, linear_release)?,
+            ReleaseCurve::ExponentialDecay { half_life } => {
+                let half_life = *half_life as u128;
+                self.cliff_vested_amount(current_time, |amount, elapsed, _| {
+                    exponential_decay_release(amount, elapsed, half_life)
+                })?
+            }
+            ReleaseCurve::Breakpoints(breakpoints) => {
+                self.breakpoint_vested_amount(breakpoints, current_time)?
+            }
+        };
+
+        Ok(vested_amount.min(self.total_amount))
     }
 
-    pub fn calculate_max_withdrawable(&self, current_time: UnixTimestamp) -> u64 {
-        if !self.is_initialized || self.is_terminated || self.is_paused {
-            return 0;
+    pub fn calculate_max_withdrawable(&self, current_time: UnixTimestamp) -> Result<u64, ProgramError> {
+        if !self.is_initialized || self.is_terminated {
+            return Ok(0);
         }
 
-        // Calculate the number of intervals elapsed since the last withdrawal
-        let elapsed_intervals = (current_time - self.last_withdraw_time) / self.interval;
+        // Everything vested so far, which for a paused stream means before the pause, that hasn't been withdrawn yet
+        self.calculate_vested_amount(current_time)?
+            .saturating_sub(self.withdrawn_amount)
+            .checked_add(self.accrued_unwithdrawn)
+            .ok_or_else(|| PaymentError::ArithmeticOverflow.into())
+    }
+
+    /// Reports the stream's status and balances at `current_time`.
+    pub fn snapshot(&self, current_time: UnixTimestamp) -> Result<StreamSnapshot, ProgramError> {
+        let vested_amount = self.calculate_vested_amount(current_time)?;
+        let remaining_amount = match self.is_terminated {
+            true => 0,
+            false => self.total_amount.saturating_sub(vested_amount),
+        };
+        let status = if self.is_terminated {
+            StreamStatus::Terminated
+        } else if self.is_paused {
+            StreamStatus::Paused
+        } else if current_time < self.start_time {
+            StreamStatus::Scheduled
+        } else if remaining_amount == 0 {
+            StreamStatus::Completed
+        } else {
+            StreamStatus::Active
+        };
 
-        // Calculate the maximum withdrawable amount
-        let max_withdrawable = self.amount_per_interval * elapsed_intervals;
+        Ok(StreamSnapshot {
+            timestamp: current_time,
+            status,
+            vested_amount: vested_amount
+                .checked_add(self.accrued_unwithdrawn)
+                .ok_or(PaymentError::ArithmeticOverflow)?,
+            withdrawable_amount: self.calculate_max_withdrawable(current_time)?,
+            remaining_amount,
+        })
+    }
 
-        // Ensure the calculated amount doesn't exceed the total amount
-        max_withdrawable.min(self.total_amount - self.withdrawn_amount)
+    pub fn is_split(&self) -> bool {
+        !self.splits.is_empty()
     }
 
-    pub fn update(&mut self, current_time: UnixTimestamp) {
-        if !self.is_initialized || self.is_terminated || self.is_paused {
-            return;
+    /// Makes this a split stream paying `splits`, each starting with nothing
+    /// withdrawn. Recipients must be distinct, weigh more than nothing and
+    /// together add up to 100%, and at most `MAX_SPLIT_RECIPIENTS` can share a
+    /// stream.
+    pub fn set_splits(&mut self, splits: Vec<SplitRecipient>) -> Result<(), ProgramError> {
+        let total_weight: u64 = splits.iter().map(|split| split.weight_bps as u64).sum();
+        let has_duplicates = splits
+            .iter()
+            .enumerate()
+            .any(|(index, split)| splits[..index].iter().any(|other| other.recipient == split.recipient));
+        if splits.len() > MAX_SPLIT_RECIPIENTS
+            || total_weight != BPS_DENOMINATOR
+            || has_duplicates
+            || splits.iter().any(|split| split.weight_bps == 0)
+        {
+            return Err(PaymentError::InvalidSplits.into());
         }
 
-        // Update the last withdrawal time based on the current time
+        self.recipient = Pubkey::default();
+        self.splits = splits
+            .into_iter()
+            .map(|split| SplitRecipient {
+                last_withdraw_time: self.last_withdraw_time,
+                ..SplitRecipient::new(split.recipient, split.weight_bps)
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Whether `key` is the recipient, or one of the split recipients, of the stream.
+    pub fn is_recipient(&self, key: &Pubkey) -> bool {
+        if self.is_split() {
+            self.splits.iter().any(|split| split.recipient == *key)
+        } else {
+            self.recipient == *key
+        }
+    }
+
+    /// What `recipient` can withdraw at `current_time`. For a split stream this
+    /// is the recipient's share of everything released so far, less what it
+    /// already withdrew.
+    pub fn withdrawable_by(&self, recipient: &Pubkey, current_time: UnixTimestamp) -> Result<u64, ProgramError> {
+        if !self.is_split() {
+            if *recipient != self.recipient {
+                return Ok(0);
+            }
+            return self.calculate_max_withdrawable(current_time);
+        }
+
+        let released_amount = self.released_to_splits(self.calculate_max_withdrawable(current_time)?)?;
+        Ok(self
+            .splits
+            .iter()
+            .find(|split| split.recipient == *recipient)
+            .map_or(0, |split| split.unwithdrawn_share(released_amount)))
+    }
+
+    /// Everything released to a split stream's recipients, withdrawn or not, when
+    /// `unwithdrawn_amount` is still waiting for them.
+    fn released_to_splits(&self, unwithdrawn_amount: u64) -> Result<u64, ProgramError> {
+        self.splits
+            .iter()
+            .try_fold(unwithdrawn_amount, |released, split| released.checked_add(split.withdrawn_amount))
+            .ok_or_else(|| PaymentError::ArithmeticOverflow.into())
+    }
+
+    /// Books a withdrawal of `amount` by `recipient`, which must be entitled to it.
+    pub fn record_withdrawal_by(&mut self, recipient: &Pubkey, amount: u64) -> Result<(), ProgramError> {
+        self.record_withdrawal(amount)?;
+        if let Some(split) = self.splits.iter_mut().find(|split| split.recipient == *recipient) {
+            split.withdrawn_amount = split
+                .withdrawn_amount
+                .checked_add(amount)
+                .ok_or(PaymentError::ArithmeticOverflow)?;
+        }
+        Ok(())
+    }
+
+    /// Notes that `recipient` withdrew at `current_time`, on its own split as well
+    /// as on the stream.
+    pub fn record_withdraw_time(&mut self, recipient: &Pubkey, current_time: UnixTimestamp) {
         self.last_withdraw_time = current_time;
+        if let Some(split) = self.splits.iter_mut().find(|split| split.recipient == *recipient) {
+            split.last_withdraw_time = current_time;
+        }
+    }
+
+    /// When `recipient` last withdrew: its own last withdrawal on a split stream,
+    /// the stream's last withdrawal otherwise.
+    pub fn last_withdraw_time_of(&self, recipient: &Pubkey) -> UnixTimestamp {
+        self.splits
+            .iter()
+            .find(|split| split.recipient == *recipient)
+            .map_or(self.last_withdraw_time, |split| split.last_withdraw_time)
+    }
+
+    /// Divides a termination `payout` among the stream's recipients, books it as
+    /// withdrawn and returns what each recipient is owed. Split recipients each
+    /// get their share of everything released, less what they withdrew, and
+    /// whatever rounding leaves over stays with the payer.
+    pub fn settle_recipients(&mut self, payout: u64) -> Result<Vec<(Pubkey, u64)>, ProgramError> {
+        if !self.is_split() {
+            self.record_withdrawal(payout)?;
+            return Ok(vec![(self.recipient, payout)]);
+        }
+
+        let released_amount = self.released_to_splits(payout)?;
+        let mut remaining = payout;
+        let mut settlements = Vec::with_capacity(self.splits.len());
+        for index in 0..self.splits.len() {
+            let split = self.splits[index];
+            let amount = split.unwithdrawn_share(released_amount).min(remaining);
+            remaining -= amount;
+            self.record_withdrawal_by(&split.recipient, amount)?;
+            settlements.push((split.recipient, amount));
+        }
+
+        Ok(settlements)
+    }
+
+    /// Hands `old_recipient`'s payouts, including what vested for it but was not
+    /// withdrawn yet, to `new_recipient`.
+    pub fn replace_recipient(&mut self, old_recipient: &Pubkey, new_recipient: &Pubkey) -> Result<(), ProgramError> {
+        if self.is_recipient(new_recipient) {
+            return Err(PaymentError::InvalidRecipient.into());
+        }
+
+        if !self.is_split() {
+            if self.recipient != *old_recipient {
+                return Err(PaymentError::InvalidRecipient.into());
+            }
+            // The new recipient starts out paid at its own address
+            self.recipient = *new_recipient;
+            self.payout_destination = None;
+            return Ok(());
+        }
+
+        let split = self
+            .splits
+            .iter_mut()
+            .find(|split| split.recipient == *old_recipient)
+            .ok_or(PaymentError::InvalidRecipient)?;
+        split.recipient = *new_recipient;
+        Ok(())
+    }
+
+    /// Books a withdrawal of `amount`, taking it from funds accrued under earlier
+    /// terms first.
+    pub fn record_withdrawal(&mut self, amount: u64) -> Result<(), ProgramError> {
+        let from_accrued = amount.min(self.accrued_unwithdrawn);
+        self.accrued_unwithdrawn -= from_accrued;
+        self.withdrawn_amount = self
+            .withdrawn_amount
+            .checked_add(amount - from_accrued)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Maps `current_time` onto the release schedule, skipping the seconds that
+    /// did not accrue. The schedule stands still while the stream is paused or
+    /// inside a blackout window.
+    fn accrual_time(&self, current_time: UnixTimestamp) -> UnixTimestamp {
+        let current_time = if self.is_paused {
+            current_time.min(self.paused_at)
+        } else {
+            current_time
+        };
+        let blackout_duration = self
+            .blackout_windows
+            .iter()
+            .fold(0u64, |duration, window| duration.saturating_add(window.elapsed(current_time)));
+
+        current_time
+            .saturating_sub_unsigned(blackout_duration)
+            .saturating_sub_unsigned(self.suspended_duration)
+            .saturating_sub_unsigned(self.total_paused_duration)
+    }
+
+    /// Replaces the blackout windows that have not begun by `current_time`.
+    /// Windows that already began are kept, since they already shaped what
+    /// vested. New windows may not begin before `current_time` or before the
+    /// stream starts, must not overlap, and at most `MAX_BLACKOUT_WINDOWS` can
+    /// be attached in total.
+    pub fn set_blackout_windows(
+        &mut self,
+        windows: Vec<BlackoutWindow>,
+        current_time: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        let earliest_start = current_time.max(self.start_time);
+        if windows.iter().any(|window| window.start < earliest_start) {
+            return Err(PaymentError::InvalidBlackoutWindows.into());
+        }
+
+        let mut blackout_windows: Vec<BlackoutWindow> = self
+            .blackout_windows
+            .iter()
+            .filter(|window| window.start < current_time)
+            .copied()
+            .collect();
+        blackout_windows.extend(windows);
+
+        if blackout_windows.len() > MAX_BLACKOUT_WINDOWS
+            || blackout_windows.iter().any(|window| window.end <= window.start)
+            || blackout_windows.windows(2).any(|pair| pair[1].start < pair[0].end)
+        {
+            return Err(PaymentError::InvalidBlackoutWindows.into());
+        }
+
+        self.blackout_windows = blackout_windows;
+        Ok(())
+    }
+
+    /// The point on the release schedule at which `total_amount` has fully vested
+    /// at the stream's current rate. Only stepped and linear streams have a rate.
+    fn scheduled_end_time(&self, total_amount: u64) -> Result<UnixTimestamp, ProgramError> {
+        let (anchor, offset) = match self.release_curve {
+            ReleaseCurve::Stepped => {
+                let amount_per_interval = self.amount_per_interval as u128;
+                let intervals = (total_amount as u128)
+                    .checked_add(amount_per_interval.saturating_sub(1))
+                    .and_then(|amount| amount.checked_div(amount_per_interval))
+                    .ok_or(PaymentError::InvalidReleaseCurve)?;
+                (self.start_time, intervals.checked_mul(self.interval as u128))
+            }
+            ReleaseCurve::Linear => {
+                // Rounding the duration down never releases less than before
+                let cliff_amount = self.cliff_amount.min(self.total_amount);
+                let rate_amount = (self.total_amount - cliff_amount) as u128;
+                let rate_duration = elapsed_seconds(self.cliff_time, self.end_time)?;
+                if rate_amount == 0 || rate_duration == 0 {
+                    return Err(PaymentError::InvalidReleaseCurve.into());
+                }
+                let amount = total_amount.saturating_sub(cliff_amount) as u128;
+                (self.cliff_time, linear_release(amount, rate_duration, rate_amount))
+            }
+            ReleaseCurve::ExponentialDecay { .. } | ReleaseCurve::Breakpoints(_) => {
+                return Err(PaymentError::InvalidReleaseCurve.into());
+            }
+        };
+
+        offset
+            .and_then(|offset| i64::try_from(offset).ok())
+            .and_then(|offset| anchor.checked_add(offset))
+            .ok_or_else(|| PaymentError::ArithmeticOverflow.into())
+    }
+
+    /// Adds `amount` to the deposit and moves `end_time` out so the stream keeps
+    /// releasing at its current rate. A stream that had run dry resumes accruing
+    /// at `current_time` rather than paying out the dry spell at once, keeping
+    /// everything it vested and paid out before. A stepped stream whose last
+    /// interval was only partly funded completes that interval first.
+    pub fn top_up(&mut self, amount: u64, current_time: UnixTimestamp) -> Result<(), ProgramError> {
+        let ran_dry_at = self.scheduled_end_time(self.total_amount)?;
+        let accrual_time = self.accrual_time(current_time);
+        if accrual_time > ran_dry_at {
+            let dry_spell = elapsed_seconds(ran_dry_at, accrual_time)? as u64;
+            self.suspended_duration = self
+                .suspended_duration
+                .checked_add(dry_spell)
+                .ok_or(PaymentError::ArithmeticOverflow)?;
+        }
+
+        let total_amount = self
+            .total_amount
+            .checked_add(amount)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+        self.end_time = self.scheduled_end_time(total_amount)?;
+        self.total_amount = total_amount;
+
+        Ok(())
+    }
+
+    fn stepped_vested_amount(&self, current_time: UnixTimestamp) -> Result<u64, ProgramError> {
+        if self.interval == 0 || current_time < self.start_time {
+            return Ok(0);
+        }
+
+        let elapsed = elapsed_seconds(self.start_time, current_time)?;
+        let elapsed_intervals = elapsed / self.interval as u128;
+        let vested_amount = elapsed_intervals
+            .checked_mul(self.amount_per_interval as u128)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+
+        Ok(vested_amount.min(self.total_amount as u128) as u64)
+    }
+
+    /// Vests nothing before `cliff_time`, everything from `end_time`, and in
+    /// between `cliff_amount` plus `release(remaining, elapsed, duration)` where
+    /// both times are measured from the cliff.
+    fn cliff_vested_amount(
+        &self,
+        current_time: UnixTimestamp,
+        release: impl Fn(u128, u128, u128) -> Option<u128>,
+    ) -> Result<u64, ProgramError> {
+        if current_time < self.cliff_time {
+            return Ok(0);
+        }
+
+        if current_time >= self.end_time {
+            return Ok(self.total_amount);
+        }
+
+        let cliff_amount = self.cliff_amount.min(self.total_amount);
+        let remaining = (self.total_amount - cliff_amount) as u128;
+        let elapsed = elapsed_seconds(self.cliff_time, current_time)?;
+        let duration = elapsed_seconds(self.cliff_time, self.end_time)?;
+
+        let released = release(remaining, elapsed, duration).ok_or(PaymentError::ArithmeticOverflow)?;
+        Ok(cliff_amount + released.min(remaining) as u64)
+    }
+
+    fn breakpoint_vested_amount(
+        &self,
+        breakpoints: &[Breakpoint],
+        current_time: UnixTimestamp,
+    ) -> Result<u64, ProgramError> {
+        let mut previous = Breakpoint {
+            timestamp: self.start_time,
+            cumulative_amount: 0,
+        };
+
+        if current_time < previous.timestamp {
+            return Ok(0);
+        }
+
+        for breakpoint in breakpoints {
+            if current_time < breakpoint.timestamp {
+                let amount = breakpoint
+                    .cumulative_amount
+                    .checked_sub(previous.cumulative_amount)
+                    .ok_or(PaymentError::ArithmeticOverflow)? as u128;
+                let elapsed = elapsed_seconds(previous.timestamp, current_time)?;
+                let duration = elapsed_seconds(previous.timestamp, breakpoint.timestamp)?;
+                let released = linear_release(amount, elapsed, duration).ok_or(PaymentError::ArithmeticOverflow)?;
+
+                return Ok(previous.cumulative_amount + released as u64);
+            }
+            previous = *breakpoint;
+        }
+
+        Ok(previous.cumulative_amount)
+    }
 
-        // Add your custom logic for updating the stream based on specific requirements
-        // For example, updating total amount, vested amount, etc.
-        // Your custom logic here
+    /// Switches a stepped stream to releasing `amount_per_interval` every
+    /// `interval` seconds from `current_time` on. What vested under the old terms
+    /// and was not withdrawn yet is checkpointed into `accrued_unwithdrawn` first,
+    /// so the new terms never apply to time that already passed. The interval in
+    /// progress is settled pro rata at the old rate, rounding up, so repeated
+    /// updates cannot keep the recipient from vesting.
+    pub fn update(
+        &mut self,
+        interval: u64,
+        amount_per_interval: u64,
+        current_time: UnixTimestamp,
+    ) -> Result<(), ProgramError> {
+        if self.release_curve != ReleaseCurve::Stepped {
+            return Err(PaymentError::InvalidReleaseCurve.into());
+        }
+
+        if interval == 0 || amount_per_interval == 0 {
+            return Err(PaymentError::InvalidTimeFrame.into());
+        }
+
+        // Before the stream starts nothing has vested, so the new terms simply
+        // replace the old ones. Otherwise they start at the current point of the
+        // schedule, which for a paused stream is where it was paused.
+        let accrual_time = self.accrual_time(current_time);
+        if accrual_time > self.start_time {
+            let interval = self.interval as u128;
+            let into_interval = elapsed_seconds(self.start_time, accrual_time)?
+                .checked_rem(interval)
+                .unwrap_or(0);
+            let in_progress_amount = (self.amount_per_interval as u128)
+                .checked_mul(into_interval)
+                .and_then(|amount| amount.checked_add(interval.saturating_sub(1)))
+                .and_then(|amount| amount.checked_div(interval))
+                .unwrap_or(0);
+            let vested_amount = (self.calculate_vested_amount(current_time)? as u128)
+                .saturating_add(in_progress_amount)
+                .min(self.total_amount as u128) as u64;
+            self.accrued_unwithdrawn = self
+                .accrued_unwithdrawn
+                .checked_add(vested_amount.saturating_sub(self.withdrawn_amount))
+                .ok_or(PaymentError::ArithmeticOverflow)?;
+            self.total_amount -= vested_amount;
+            self.withdrawn_amount = 0;
+            self.start_time = accrual_time;
+        }
+
+        self.interval = interval;
+        self.amount_per_interval = amount_per_interval;
+        self.end_time = self.scheduled_end_time(self.total_amount)?;
+
+        Ok(())
+    }
+
+    /// Returns what the recipient is owed when `terminated_by` ends the stream at
+    /// `current_time`. Vested funds belong to the recipient and unvested funds to
+    /// the payer, and whoever terminates forfeits `cancellation_penalty_bps` of
+    /// its own share to the other party: a payer of the unvested funds, a
+    /// recipient of the vested funds. The payer is refunded whatever the recipient
+    /// is not owed.
+    pub fn termination_payout(
+        &self,
+        current_time: UnixTimestamp,
+        terminated_by: StreamParty,
+    ) -> Result<u64, ProgramError> {
+        let vested_amount = self.calculate_max_withdrawable(current_time)?;
+        let unvested_amount = self
+            .total_amount
+            .saturating_sub(self.withdrawn_amount)
+            .saturating_sub(vested_amount.saturating_sub(self.accrued_unwithdrawn));
+
+        match terminated_by {
+            StreamParty::Payer => vested_amount
+                .checked_add(calculate_fee(unvested_amount, self.cancellation_penalty_bps)?)
+                .ok_or_else(|| PaymentError::ArithmeticOverflow.into()),
+            StreamParty::Recipient => vested_amount
+                .checked_sub(calculate_fee(vested_amount, self.cancellation_penalty_bps)?)
+                .ok_or_else(|| PaymentError::ArithmeticOverflow.into()),
+        }
+    }
+
+    /// Whether `party` may reassign the stream's recipient.
+    pub fn is_transferable_by(&self, party: StreamParty) -> bool {
+        match party {
+            StreamParty::Payer => self.transferable_by_sender,
+            StreamParty::Recipient => self.transferable_by_recipient,
+        }
     }
 
     pub fn terminate(&mut self) {
         if self.is_initialized && !self.is_terminated {
-            // Add your custom logic for terminating the stream based on specific requirements
-            // For example, handle early termination penalties, transfer remaining funds, etc.
-            // Your custom logic here
-
             self.is_terminated = true;
         }
     }
 
-    pub fn pause(&mut self) {
+    /// Stops the stream from vesting at `current_time`. What vested before stays
+    /// withdrawable.
+    pub fn pause(&mut self, current_time: UnixTimestamp) {
         if self.is_initialized && !self.is_terminated && !self.is_paused {
-            // Add your custom logic for pausing the stream based on specific requirements
-            // For example, implement a pause mechanism, suspend interval updates, etc.
-            // Your custom logic here
-
             self.is_paused = true;
+            self.paused_at = current_time;
         }
     }
 
-    pub fn resume(&mut self) {
+    /// Lets the stream vest again from `current_time`, excluding the time it
+    /// spent paused. Time paused before the stream started would not have
+    /// vested anyway, and time inside blackout windows is excluded already, so
+    /// neither is counted again.
+    pub fn resume(&mut self, current_time: UnixTimestamp) {
         if self.is_initialized && !self.is_terminated && self.is_paused {
-            // Add your custom logic for resuming the stream based on specific requirements
-            // For example, resume interval updates, restart payments, etc.
-            // Your custom logic here
-
+            let paused_from = self.accrual_time(current_time).max(self.start_time);
             self.is_paused = false;
+            let resumed_from = self.accrual_time(current_time).max(self.start_time);
+
+            let paused_duration = resumed_from.saturating_sub(paused_from).max(0) as u64;
+            self.total_paused_duration = self.total_paused_duration.saturating_add(paused_duration);
+            self.paused_at = 0;
         }
     }
 
     // Add more custom methods as needed for program-specific functionality
 }
+
+/// Seconds from `from` to `to`, or an overflow error if `to` is earlier.
+fn elapsed_seconds(from: UnixTimestamp, to: UnixTimestamp) -> Result<u128, ProgramError> {
+    to.checked_sub(from)
+        .and_then(|elapsed| u128::try_from(elapsed).ok())
+        .ok_or_else(|| PaymentError::ArithmeticOverflow.into())
+}
+
+/// Releases `amount * elapsed / duration`, rounding down.
+fn linear_release(amount: u128, elapsed: u128, duration: u128) -> Option<u128> {
+    amount.checked_mul(elapsed)?.checked_div(duration)
+}
+
+/// Releases `amount` minus what is left after halving it once per `half_life`,
+/// interpolating linearly within the current half-life, rounding down.
+fn exponential_decay_release(amount: u128, elapsed: u128, half_life: u128) -> Option<u128> {
+    let half_lives = elapsed.checked_div(half_life)?;
+    let partial = elapsed.checked_rem(half_life)?;
+    if half_lives >= 128 {
+        return Some(amount);
+    }
+
+    let unvested = amount >> half_lives;
+    let next_unvested = unvested >> 1;
+    let released_in_half_life = (unvested - next_unvested).checked_mul(partial)?.checked_div(half_life)?;
+
+    amount.checked_sub(unvested)?.checked_add(released_in_half_life)
+}
+
+/// Layout version 1 of `PaymentStream`, which had no `created_at`.
+#[derive(BorshDeserialize)]
+struct PaymentStreamV1 {
+    payer: Pubkey,
+    recipient: Pubkey,
+    delegate: Option<Pubkey>,
+    start_time: UnixTimestamp,
+    interval: u64,
+    amount_per_interval: u64,
+    total_amount: u64,
+    withdrawn_amount: u64,
+    accrued_unwithdrawn: u64,
+    last_withdraw_time: UnixTimestamp,
+    cliff_time: UnixTimestamp,
+    cliff_amount: u64,
+    end_time: UnixTimestamp,
+    release_curve: ReleaseCurve,
+    suspended_duration: u64,
+    cancellation_policy: CancellationPolicy,
+    cancellation_penalty_bps: u16,
+    transferable_by_recipient: bool,
+    transferable_by_sender: bool,
+    update_requires_recipient_consent: bool,
+    allow_crank: bool,
+    is_initialized: bool,
+    is_terminated: bool,
+    is_paused: bool,
+    paused_at: UnixTimestamp,
+    total_paused_duration: u64,
+    blackout_windows: Vec<BlackoutWindow>,
+    splits: Vec<SplitRecipientV3>,
+    vault_bump: u8,
+    is_spl_token: bool,
+    mint: Pubkey,
+    transfer_fees_withheld: u64,
+    fees_paid: u64,
+}
+
+impl From<PaymentStreamV1> for PaymentStream {
+    fn from(v1: PaymentStreamV1) -> Self {
+        PaymentStream {
+            payer: v1.payer,
+            recipient: v1.recipient,
+            delegate: v1.delegate,
+            start_time: v1.start_time,
+            interval: v1.interval,
+            amount_per_interval: v1.amount_per_interval,
+            total_amount: v1.total_amount,
+            withdrawn_amount: v1.withdrawn_amount,
+            accrued_unwithdrawn: v1.accrued_unwithdrawn,
+            last_withdraw_time: v1.last_withdraw_time,
+            cliff_time: v1.cliff_time,
+            cliff_amount: v1.cliff_amount,
+            end_time: v1.end_time,
+            release_curve: v1.release_curve,
+            suspended_duration: v1.suspended_duration,
+            cancellation_policy: v1.cancellation_policy,
+            cancellation_penalty_bps: v1.cancellation_penalty_bps,
+            transferable_by_recipient: v1.transferable_by_recipient,
+            transferable_by_sender: v1.transferable_by_sender,
+            update_requires_recipient_consent: v1.update_requires_recipient_consent,
+            allow_crank: v1.allow_crank,
+            is_initialized: v1.is_initialized,
+            is_terminated: v1.is_terminated,
+            is_paused: v1.is_paused,
+            paused_at: v1.paused_at,
+            total_paused_duration: v1.total_paused_duration,
+            blackout_windows: v1.blackout_windows,
+            // Until they withdraw again, each recipient is paced by the stream's last withdrawal
+            splits: v1
+                .splits
+                .into_iter()
+                .map(|split| SplitRecipient {
+                    recipient: split.recipient,
+                    weight_bps: split.weight_bps,
+                    withdrawn_amount: split.withdrawn_amount,
+                    last_withdraw_time: v1.last_withdraw_time,
+                })
+                .collect(),
+            vault_bump: v1.vault_bump,
+            is_spl_token: v1.is_spl_token,
+            mint: v1.mint,
+            transfer_fees_withheld: v1.transfer_fees_withheld,
+            fees_paid: v1.fees_paid,
+            created_at: 0,
+            payout_destination: None,
+        }
+    }
+}
+
+/// Split recipient of layout versions 1 to 3, which had no `last_withdraw_time`.
+#[derive(BorshDeserialize)]
+struct SplitRecipientV3 {
+    recipient: Pubkey,
+    weight_bps: u16,
+    withdrawn_amount: u64,
+}
+
+/// Layout versions 2 and 3 of `PaymentStream`, which version 2 ended at
+/// `created_at`. Version 4 added `last_withdraw_time` to split recipients.
+#[derive(BorshDeserialize)]
+struct PaymentStreamV3 {
+    v1: PaymentStreamV1,
+    created_at: UnixTimestamp,
+    payout_destination: Option<Pubkey>,
+}
+
+impl From<PaymentStreamV3> for PaymentStream {
+    fn from(v3: PaymentStreamV3) -> Self {
+        PaymentStream {
+            created_at: v3.created_at,
+            payout_destination: v3.payout_destination,
+            ..Self::from(v3.v1)
+        }
+    }
+}
@@ -1,20 +1,74 @@
+use borsh::BorshSerialize;
 use crate::{
-    instruction::StreamPayInstruction,
-    state::PaymentStream,
-    error::{StreamError, PaymentError},
-    constants::{MINIMUM_AMOUNT, OPERATIONAL_FEE_RATE, MAX_WITHDRAWAL_AMOUNT},
+    instruction::{StreamEntry, StreamPayInstruction},
+    state::{
+        BlackoutWindow, CancellationPolicy, ConfigParams, PaymentStream, ProgramConfig, ReleaseCurve, SplitRecipient,
+        StreamParty,
+    },
+    error::PaymentError,
+    events::StreamEvent,
+    constants::{BPS_DENOMINATOR, CONFIG_SEED, MAX_BATCH_STREAMS, MIN_CRANK_INTERVAL},
+    utils::{
+        calculate_fee, calculate_share, calculate_transfer_fee, check_mint_extensions, check_token_account, check_token_program,
+        check_vault_address, close_vault_token_account, create_pda_account, current_timestamp,
+        find_config_address, find_treasury_address, find_vault_address, token_account_owner, token_balance,
+        transfer_from_treasury, transfer_from_vault, transfer_tokens_from_treasury, transfer_tokens_from_vault,
+        unpack_mint,
+    },
 };
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::UnixTimestamp,
     entrypoint::ProgramResult,
-    msg,
-    program::invoke,
+    program::{invoke, set_return_data},
     program_error::ProgramError,
     pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
 };
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use std::slice::Iter;
 
 pub struct Processor;
 
+/// Token accounts that follow the common accounts of an instruction on an SPL token stream.
+struct TokenEscrowAccounts<'a, 'info> {
+    mint: &'a AccountInfo<'info>,
+    vault_token: &'a AccountInfo<'info>,
+    token_program: &'a AccountInfo<'info>,
+}
+
+impl<'a, 'info> TokenEscrowAccounts<'a, 'info> {
+    /// Reads the mint, vault token account and token program, checking them against the stream.
+    fn next(
+        account_info_iter: &mut Iter<'a, AccountInfo<'info>>,
+        payment_stream: &PaymentStream,
+        vault: &Pubkey,
+    ) -> Result<Self, ProgramError> {
+        let mint = next_account_info(account_info_iter)?;
+        let vault_token = next_account_info(account_info_iter)?;
+        let token_program = next_account_info(account_info_iter)?;
+
+        check_token_program(token_program.key)?;
+
+        if *mint.key != payment_stream.mint || mint.owner != token_program.key {
+            return Err(PaymentError::InvalidMint.into());
+        }
+
+        check_token_account(vault_token, mint.key, vault)?;
+
+        Ok(Self {
+            mint,
+            vault_token,
+            token_program,
+        })
+    }
+}
+
 impl Processor {
     pub fn process(
         program_id: &Pubkey,
@@ -22,112 +76,636 @@ impl Processor {
         instruction_data: &[u8],
     ) -> ProgramResult {
         let instruction = StreamPayInstruction::unpack(instruction_data)?;
+        Self::check_program_not_paused(program_id, accounts, &instruction)?;
 
         match instruction {
             StreamPayInstruction::InitializeStream {
                 start_time,
                 interval,
                 amount_per_interval,
+                total_amount,
+                cliff_time,
+                cliff_amount,
+                end_time,
+                release_curve,
+                delegate,
+                cancellation_policy,
+                cancellation_penalty_bps,
+                transferable_by_recipient,
+                transferable_by_sender,
+                update_requires_recipient_consent,
+                allow_crank,
+                blackout_windows,
+                splits,
+                mint,
             } => {
-                msg!("Initialize payment stream instruction received");
-                Self::initialize_stream(program_id, accounts, start_time, interval, amount_per_interval)
+                Self::initialize_stream(
+                    program_id,
+                    accounts,
+                    start_time,
+                    interval,
+                    amount_per_interval,
+                    total_amount,
+                    cliff_time,
+                    cliff_amount,
+                    end_time,
+                    release_curve,
+                    delegate,
+                    cancellation_policy,
+                    cancellation_penalty_bps,
+                    transferable_by_recipient,
+                    transferable_by_sender,
+                    update_requires_recipient_consent,
+                    allow_crank,
+                    blackout_windows,
+                    splits,
+                    mint,
+                )
             }
             StreamPayInstruction::UpdateStream {
                 interval,
                 amount_per_interval,
             } => {
-                msg!("Update payment stream instruction received");
-                Self::update_stream(accounts, interval, amount_per_interval)
+                Self::update_stream(program_id, accounts, interval, amount_per_interval)
             }
             StreamPayInstruction::TerminateStream => {
-                msg!("Terminate payment stream instruction received");
                 Self::terminate_stream(program_id, accounts)
             }
             StreamPayInstruction::Withdraw { amount } => {
-                msg!("Withdraw instruction received");
-                Self::withdraw(program_id, accounts, amount)
+                Self::withdraw(program_id, accounts, Some(amount), false)
+            }
+            StreamPayInstruction::WithdrawMax => {
+                Self::withdraw(program_id, accounts, None, false)
+            }
+            StreamPayInstruction::CrankWithdraw => {
+                Self::withdraw(program_id, accounts, None, true)
             }
             StreamPayInstruction::PauseStream => {
-                msg!("Pause payment stream instruction received");
                 Self::pause_stream(program_id, accounts)
             }
             StreamPayInstruction::ResumeStream => {
-                msg!("Resume payment stream instruction received");
                 Self::resume_stream(program_id, accounts)
             }
             StreamPayInstruction::QueryStream => {
-                msg!("Query payment stream instruction received");
                 Self::query_stream(program_id, accounts)
             }
+            StreamPayInstruction::InitializeConfig { params } => {
+                Self::initialize_config(program_id, accounts, params)
+            }
+            StreamPayInstruction::UpdateConfig { params } => {
+                Self::update_config(program_id, accounts, params)
+            }
+            StreamPayInstruction::TransferAdmin => {
+                Self::transfer_admin(program_id, accounts)
+            }
+            StreamPayInstruction::SetProgramPaused {
+                is_paused,
+                allow_vested_withdrawals,
+            } => {
+                Self::set_program_paused(program_id, accounts, is_paused, allow_vested_withdrawals)
+            }
+            StreamPayInstruction::CollectFees => {
+                Self::collect_fees(program_id, accounts)
+            }
+            StreamPayInstruction::QueryTreasury => {
+                Self::query_treasury(program_id, accounts)
+            }
+            StreamPayInstruction::TransferRecipient => {
+                Self::transfer_recipient(program_id, accounts)
+            }
+            StreamPayInstruction::TopUp { amount } => {
+                Self::top_up(program_id, accounts, amount)
+            }
+            StreamPayInstruction::RequireUpdateConsent => {
+                Self::require_update_consent(program_id, accounts)
+            }
+            StreamPayInstruction::UpdateBlackoutWindows { blackout_windows } => {
+                Self::update_blackout_windows(program_id, accounts, blackout_windows)
+            }
+            StreamPayInstruction::BatchInitializeStreams { streams, mint } => {
+                Self::batch_initialize_streams(program_id, accounts, streams, mint)
+            }
+            StreamPayInstruction::MigrateStream => {
+                Self::migrate_stream(program_id, accounts)
+            }
+            StreamPayInstruction::SetPayoutDestination => {
+                Self::set_payout_destination(program_id, accounts)
+            }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn initialize_stream(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         start_time: u64,
         interval: u64,
         amount_per_interval: u64,
+        total_amount: u64,
+        cliff_time: u64,
+        cliff_amount: u64,
+        end_time: u64,
+        release_curve: ReleaseCurve,
+        delegate: Option<Pubkey>,
+        cancellation_policy: CancellationPolicy,
+        cancellation_penalty_bps: u16,
+        transferable_by_recipient: bool,
+        transferable_by_sender: bool,
+        update_requires_recipient_consent: bool,
+        allow_crank: bool,
+        blackout_windows: Vec<BlackoutWindow>,
+        splits: Vec<SplitRecipient>,
+        mint: Option<Pubkey>,
     ) -> ProgramResult {
         // Ensure correct account permissions
         let account_info_iter = &mut accounts.iter();
         let payer_account = next_account_info(account_info_iter)?;
         let payment_stream_account = next_account_info(account_info_iter)?;
+        let recipient_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
 
         if payment_stream_account.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
-        // Create and initialize the payment stream state
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        if !payer_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
 
-        if payment_stream.is_initialized {
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
+        // The deposit is escrowed in a vault PDA derived from the stream key
+        let (vault_address, vault_bump) = find_vault_address(payment_stream_account.key, program_id);
+        if *vault_account.key != vault_address {
+            return Err(PaymentError::InvalidVaultAccount.into());
+        }
+
+        // Create and initialize the payment stream state. The client creates the
+        // account zeroed, so anything else is a stream or another program account.
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        if PaymentStream::is_stream_account(&payment_stream_data) {
             return Err(PaymentError::StreamAlreadyInitialized.into());
         }
+        if payment_stream_data.iter().any(|byte| *byte != 0) {
+            return Err(PaymentError::InvalidAccountType.into());
+        }
+        let mut payment_stream = PaymentStream::new(*payer_account.key, *recipient_account.key);
+
+        // SPL token streams pass the mint and token accounts after the system program
+        let mint_account = Self::next_mint_account(account_info_iter, mint.as_ref())?;
+        let entry = StreamEntry {
+            recipient: *recipient_account.key,
+            start_time,
+            interval,
+            amount_per_interval,
+            total_amount,
+            cliff_time,
+            cliff_amount,
+            end_time,
+            release_curve,
+        };
+        let deposit_fee =
+            Self::open_stream(&mut payment_stream, &config, payer_account.key, vault_bump, &entry, mint_account)?;
+
+        payment_stream.delegate = delegate;
+        payment_stream.cancellation_policy = cancellation_policy;
+        payment_stream.cancellation_penalty_bps = cancellation_penalty_bps;
+        payment_stream.transferable_by_recipient = transferable_by_recipient;
+        payment_stream.transferable_by_sender = transferable_by_sender;
+        payment_stream.update_requires_recipient_consent = update_requires_recipient_consent;
+        payment_stream.allow_crank = allow_crank;
+
+        if u64::from(cancellation_penalty_bps) > BPS_DENOMINATOR {
+            return Err(PaymentError::InvalidPenaltyRate.into());
+        }
+
+        payment_stream.set_blackout_windows(blackout_windows, payment_stream.start_time)?;
+        if !splits.is_empty() {
+            payment_stream.set_splits(splits)?;
+        }
+
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::created(payment_stream_account.key, &payment_stream).emit();
+
+        match mint_account {
+            Some(mint_account) => Self::deposit_tokens(
+                account_info_iter,
+                payer_account,
+                mint_account,
+                vault_account,
+                treasury_account,
+                system_program_account,
+                total_amount,
+                deposit_fee,
+            ),
+            None => {
+                Self::deposit_lamports(payer_account, vault_account, system_program_account, total_amount)?;
+
+                if deposit_fee > 0 {
+                    Self::transfer_funds(payer_account, treasury_account, system_program_account, deposit_fee)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn batch_initialize_streams(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        streams: Vec<StreamEntry>,
+        mint: Option<Pubkey>,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        if !payer_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
+
+        // Every stream costs a few CPIs, so a batch is capped to stay within the
+        // compute budget of one transaction
+        if streams.is_empty() || streams.len() > MAX_BATCH_STREAMS {
+            return Err(PaymentError::InvalidBatchSize.into());
+        }
+
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+        let stream_accounts = streams
+            .iter()
+            .map(|_| Ok((next_account_info(account_info_iter)?, next_account_info(account_info_iter)?)))
+            .collect::<Result<Vec<_>, ProgramError>>()?;
+
+        // SPL token batches pass the mint and the token accounts shared by every
+        // stream after the streams, then the vault token account of each stream
+        let mint_account = Self::next_mint_account(account_info_iter, mint.as_ref())?;
+        let shared_token_accounts = match mint_account {
+            Some(_) => Some([
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+                next_account_info(account_info_iter)?,
+            ]),
+            None => None,
+        };
+
+        let rent = Rent::get()?;
+        let mut lamport_fees: u64 = 0;
+        for (entry, (payment_stream_account, vault_account)) in streams.iter().zip(stream_accounts) {
+            let (vault_address, vault_bump) = find_vault_address(payment_stream_account.key, program_id);
+            if *vault_account.key != vault_address {
+                return Err(PaymentError::InvalidVaultAccount.into());
+            }
+
+            let mut payment_stream = PaymentStream::new(*payer_account.key, entry.recipient);
+            let deposit_fee =
+                Self::open_stream(&mut payment_stream, &config, payer_account.key, vault_bump, entry, mint_account)?;
+
+            // The new stream account signs the transaction, so the payer can fund
+            // its creation directly
+            let space = PaymentStream::LEN;
+            invoke(
+                &system_instruction::create_account(
+                    payer_account.key,
+                    payment_stream_account.key,
+                    rent.minimum_balance(space),
+                    space as u64,
+                    program_id,
+                ),
+                &[
+                    payer_account.clone(),
+                    payment_stream_account.clone(),
+                    system_program_account.clone(),
+                ],
+            )?;
+            payment_stream.pack(&mut payment_stream_account.try_borrow_mut_data()?)?;
+            StreamEvent::created(payment_stream_account.key, &payment_stream).emit();
+
+            match (mint_account, shared_token_accounts) {
+                (Some(mint_account), Some([token_program, payer_token, associated_token_program, treasury_token])) => {
+                    let token_accounts = [
+                        next_account_info(account_info_iter)?.clone(),
+                        token_program.clone(),
+                        payer_token.clone(),
+                        associated_token_program.clone(),
+                        treasury_token.clone(),
+                    ];
+                    Self::deposit_tokens(
+                        &mut token_accounts.iter(),
+                        payer_account,
+                        mint_account,
+                        vault_account,
+                        treasury_account,
+                        system_program_account,
+                        entry.total_amount,
+                        deposit_fee,
+                    )?;
+                }
+                _ => {
+                    Self::deposit_lamports(payer_account, vault_account, system_program_account, entry.total_amount)?;
+                    lamport_fees = lamport_fees
+                        .checked_add(deposit_fee)
+                        .ok_or(PaymentError::ArithmeticOverflow)?;
+                }
+            }
+        }
+
+        if lamport_fees > 0 {
+            Self::transfer_funds(payer_account, treasury_account, system_program_account, lamport_fees)?;
+        }
+
+        Ok(())
+    }
 
-        let current_time = Self::current_timestamp();
+    /// Fills in a new stream's payer, recipient, schedule and mint from `entry`
+    /// and validates them, returning the deposit fee owed on top of
+    /// `entry.total_amount`. Token streams pass their mint.
+    fn open_stream(
+        payment_stream: &mut PaymentStream,
+        config: &ProgramConfig,
+        payer: &Pubkey,
+        vault_bump: u8,
+        entry: &StreamEntry,
+        mint_account: Option<&AccountInfo>,
+    ) -> Result<u64, ProgramError> {
+        let start_time = UnixTimestamp::try_from(entry.start_time).map_err(|_| PaymentError::InvalidStartTime)?;
+        let current_time = current_timestamp()?;
         if start_time < current_time {
-            return Err(StreamError::InvalidStartTime.into());
+            return Err(PaymentError::InvalidStartTime.into());
         }
 
-        if amount_per_interval < MINIMUM_AMOUNT {
+        if entry.total_amount < config.minimum_deposit {
             return Err(PaymentError::InvalidAmount.into());
         }
 
-        // Initialize other state variables and transitions
         payment_stream.is_initialized = true;
-        payment_stream.owner = *payer_account.key;
+        payment_stream.payer = *payer;
+        payment_stream.recipient = entry.recipient;
         payment_stream.start_time = start_time;
-        payment_stream.interval = interval;
-        payment_stream.amount_per_interval = amount_per_interval;
+        payment_stream.last_withdraw_time = start_time;
+        payment_stream.interval = entry.interval;
+        payment_stream.amount_per_interval = entry.amount_per_interval;
+        payment_stream.total_amount = entry.total_amount;
+        payment_stream.cliff_time =
+            UnixTimestamp::try_from(entry.cliff_time).map_err(|_| PaymentError::InvalidTimeFrame)?;
+        payment_stream.cliff_amount = entry.cliff_amount;
+        payment_stream.end_time = UnixTimestamp::try_from(entry.end_time).map_err(|_| PaymentError::InvalidTimeFrame)?;
+        payment_stream.release_curve = entry.release_curve.clone();
+        payment_stream.vault_bump = vault_bump;
+        payment_stream.created_at = current_time;
+
+        // The deposit fee is paid on top of `total_amount`, straight into the treasury
+        if config.fee_charge_point.charges_deposit() {
+            payment_stream.fees_paid = calculate_fee(entry.total_amount, config.fee_bps)?;
+        }
+
+        if let Some(mint_account) = mint_account {
+            check_mint_extensions(mint_account)?;
+
+            // Token-2022 transfer fees are withheld from the deposit, so the stream
+            // only tracks what actually lands in the vault
+            let transfer_fee = calculate_transfer_fee(mint_account, entry.total_amount)?;
+            payment_stream.total_amount = entry
+                .total_amount
+                .checked_sub(transfer_fee)
+                .ok_or(PaymentError::InvalidAmount)?;
+        }
+        payment_stream.is_spl_token = mint_account.is_some();
+        payment_stream.mint = mint_account.map(|mint| *mint.key).unwrap_or_default();
+
+        // The schedule is checked against what the vault was actually credited, so
+        // no release curve can promise more than the stream holds
+        payment_stream.validate_schedule()?;
+
+        Ok(payment_stream.fees_paid)
+    }
+
+    /// Moves a lamport deposit into a stream's vault. The vault is a plain system
+    /// account, so the first deposit also covers its rent-exempt reserve.
+    fn deposit_lamports<'a>(
+        payer_account: &AccountInfo<'a>,
+        vault_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        let rent_reserve = Rent::get()?
+            .minimum_balance(0)
+            .saturating_sub(vault_account.lamports());
+        Self::transfer_funds(payer_account, vault_account, system_program_account, amount + rent_reserve)
+    }
+
+    /// Creates the vault's and the treasury's associated token accounts, moves the
+    /// deposit into the vault and the deposit fee into the treasury.
+    #[allow(clippy::too_many_arguments)]
+    fn deposit_tokens<'a, 'info>(
+        account_info_iter: &mut Iter<'a, AccountInfo<'info>>,
+        payer_account: &'a AccountInfo<'info>,
+        mint_account: &'a AccountInfo<'info>,
+        vault_account: &'a AccountInfo<'info>,
+        treasury_account: &'a AccountInfo<'info>,
+        system_program_account: &'a AccountInfo<'info>,
+        amount: u64,
+        fee: u64,
+    ) -> ProgramResult {
+        let vault_token_account = next_account_info(account_info_iter)?;
+        let token_program_account = next_account_info(account_info_iter)?;
+        let payer_token_account = next_account_info(account_info_iter)?;
+        let associated_token_program_account = next_account_info(account_info_iter)?;
+        let treasury_token_account = next_account_info(account_info_iter)?;
+
+        check_token_program(token_program_account.key)?;
+        if *associated_token_program_account.key != spl_associated_token_account::id() {
+            return Err(ProgramError::IncorrectProgramId);
+        }
 
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
+        if mint_account.owner != token_program_account.key {
+            return Err(PaymentError::InvalidMint.into());
+        }
+
+        let mint = unpack_mint(mint_account)?;
+        check_token_account(payer_token_account, mint_account.key, payer_account.key)?;
+
+        // The treasury's token account is created alongside the vault's so later
+        // withdrawal fees in this mint always have somewhere to go
+        for (owner_account, token_account, amount) in [
+            (vault_account, vault_token_account, amount),
+            (treasury_account, treasury_token_account, fee),
+        ] {
+            let token_address = get_associated_token_address_with_program_id(
+                owner_account.key,
+                mint_account.key,
+                token_program_account.key,
+            );
+            if *token_account.key != token_address {
+                return Err(PaymentError::InvalidTokenAccount.into());
+            }
 
-        // Deduct the initial amount from the payer's account
-        let withdrawal_amount = amount_per_interval + Self::calculate_operational_fee(amount_per_interval);
-        Self::transfer_funds(payer_account, payment_stream_account, withdrawal_amount)?;
+            invoke(
+                &create_associated_token_account_idempotent(
+                    payer_account.key,
+                    owner_account.key,
+                    mint_account.key,
+                    token_program_account.key,
+                ),
+                &[
+                    payer_account.clone(),
+                    token_account.clone(),
+                    owner_account.clone(),
+                    mint_account.clone(),
+                    system_program_account.clone(),
+                    token_program_account.clone(),
+                    associated_token_program_account.clone(),
+                ],
+            )?;
+
+            if amount == 0 {
+                continue;
+            }
+
+            invoke(
+                &spl_token_2022::instruction::transfer_checked(
+                    token_program_account.key,
+                    payer_token_account.key,
+                    mint_account.key,
+                    token_account.key,
+                    payer_account.key,
+                    &[],
+                    amount,
+                    mint.decimals,
+                )?,
+                &[
+                    payer_token_account.clone(),
+                    mint_account.clone(),
+                    token_account.clone(),
+                    payer_account.clone(),
+                    token_program_account.clone(),
+                ],
+            )?;
+        }
 
         Ok(())
     }
 
+    fn top_up(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: u64,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let funder_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !funder_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
+
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        if payment_stream.is_terminated {
+            return Err(PaymentError::StreamAlreadyTerminated.into());
+        }
+
+        check_vault_address(vault_account.key, payment_stream_account.key, payment_stream.vault_bump, program_id)?;
+
+        // Top-ups are held to the same minimum as initial deposits
+        if amount < config.minimum_deposit {
+            return Err(PaymentError::InvalidAmount.into());
+        }
+
+        // Like the initial deposit, the fee is paid on top of the top-up
+        let fee = if config.fee_charge_point.charges_deposit() {
+            calculate_fee(amount, config.fee_bps)?
+        } else {
+            0
+        };
+        payment_stream.fees_paid = payment_stream
+            .fees_paid
+            .checked_add(fee)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+
+        let mint = payment_stream.is_spl_token.then_some(payment_stream.mint);
+        let mint_account = Self::next_mint_account(account_info_iter, mint.as_ref())?;
+
+        // Token-2022 transfer fees are withheld on the way into the vault
+        let credited_amount = match mint_account {
+            Some(mint_account) => amount
+                .checked_sub(calculate_transfer_fee(mint_account, amount)?)
+                .ok_or(PaymentError::InvalidAmount)?,
+            None => amount,
+        };
+        payment_stream.top_up(credited_amount, current_timestamp()?)?;
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::ToppedUp {
+            payment_stream: *payment_stream_account.key,
+            funder: *funder_account.key,
+            amount: credited_amount,
+            total_amount: payment_stream.total_amount,
+            end_time: payment_stream.end_time,
+        }
+        .emit();
+
+        match mint_account {
+            Some(mint_account) => Self::deposit_tokens(
+                account_info_iter,
+                funder_account,
+                mint_account,
+                vault_account,
+                treasury_account,
+                system_program_account,
+                amount,
+                fee,
+            ),
+            None => {
+                Self::transfer_funds(funder_account, vault_account, system_program_account, amount)?;
+
+                if fee > 0 {
+                    Self::transfer_funds(funder_account, treasury_account, system_program_account, fee)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
     fn update_stream(
+        program_id: &Pubkey,
         accounts: &[AccountInfo],
         interval: u64,
         amount_per_interval: u64,
     ) -> ProgramResult {
         // Check account permissions
         let account_info_iter = &mut accounts.iter();
-        let program_id = next_account_info(account_info_iter)?; // Add this line
+        let authority_account = next_account_info(account_info_iter)?;
         let payment_stream_account = next_account_info(account_info_iter)?;
 
         // Verify that the payment stream is owned by the program
-        if payment_stream_account.owner != program_id.key {
+        if payment_stream_account.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
         let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
         let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        Self::check_payer_or_delegate(&payment_stream, authority_account)?;
 
         if !payment_stream.is_initialized {
             return Err(PaymentError::StreamNotInitialized.into());
@@ -137,29 +715,38 @@ impl Processor {
             return Err(PaymentError::StreamAlreadyTerminated.into());
         }
 
-        // Update the payment stream properties
-        payment_stream.update(interval, amount_per_interval);
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
+        Self::check_update_consent(program_id, &payment_stream, account_info_iter)?;
+
+        // Settle what vested under the old terms before the new ones take effect
+        payment_stream.update(interval, amount_per_interval, current_timestamp()?)?;
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::Updated {
+            payment_stream: *payment_stream_account.key,
+            interval: payment_stream.interval,
+            amount_per_interval: payment_stream.amount_per_interval,
+            end_time: payment_stream.end_time,
+        }
+        .emit();
 
         Ok(())
     }
 
-    fn terminate_stream(
+    fn update_blackout_windows(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
+        blackout_windows: Vec<BlackoutWindow>,
     ) -> ProgramResult {
-        // Check account permissions
         let account_info_iter = &mut accounts.iter();
-        let payer_account = next_account_info(account_info_iter)?;
+        let authority_account = next_account_info(account_info_iter)?;
         let payment_stream_account = next_account_info(account_info_iter)?;
 
-        // Verify that the payment stream is owned by the program
         if payment_stream_account.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
         let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
         let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        Self::check_payer_or_delegate(&payment_stream, authority_account)?;
 
         if !payment_stream.is_initialized {
             return Err(PaymentError::StreamNotInitialized.into());
@@ -169,41 +756,25 @@ impl Processor {
             return Err(PaymentError::StreamAlreadyTerminated.into());
         }
 
-        // Implement logic to terminate the payment stream
-        payment_stream.terminate(); // For example, set payment_stream.is_terminated = true;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
+        Self::check_update_consent(program_id, &payment_stream, account_info_iter)?;
 
-        Ok(())
-    }
+        payment_stream.set_blackout_windows(blackout_windows, current_timestamp()?)?;
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::BlackoutWindowsUpdated {
+            payment_stream: *payment_stream_account.key,
+            blackout_windows: payment_stream.blackout_windows.clone(),
+        }
+        .emit();
 
-    fn transfer_funds(
-        from_account: &AccountInfo,
-        to_account: &AccountInfo,
-        amount: u64,
-    ) -> ProgramResult {
-        // Implement logic to transfer funds between accounts
-        // You can use the solana_program::program::invoke function for token transfers
-        // For example:
-        // invoke(
-        //     &transfer(&from_account.key, &to_account.key, amount),
-        //     &[from_account.clone(), to_account.clone()],
-        // )?;
-        // Ensure that you handle any errors that might occur during the transfer.
-
-        // Placeholder implementation
-        // You should replace this with the actual logic for token transfer
         Ok(())
     }
 
-    fn pause_stream(
+    fn require_update_consent(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        // Check account permissions and state transitions
-
-        // Verify that the payment stream is owned by the program
         let account_info_iter = &mut accounts.iter();
-        let payer_account = next_account_info(account_info_iter)?;
+        let authority_account = next_account_info(account_info_iter)?;
         let payment_stream_account = next_account_info(account_info_iter)?;
 
         if payment_stream_account.owner != program_id {
@@ -212,6 +783,7 @@ impl Processor {
 
         let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
         let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        Self::check_party(&payment_stream, authority_account)?;
 
         if !payment_stream.is_initialized {
             return Err(PaymentError::StreamNotInitialized.into());
@@ -221,32 +793,39 @@ impl Processor {
             return Err(PaymentError::StreamAlreadyTerminated.into());
         }
 
-        if payment_stream.is_paused {
-            return Err(PaymentError::StreamAlreadyPaused.into());
+        // There is deliberately no way to turn the requirement off again
+        payment_stream.update_requires_recipient_consent = true;
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::UpdateConsentRequired {
+            payment_stream: *payment_stream_account.key,
+            required_by: *authority_account.key,
         }
-
-        // Implement logic to pause the payment stream
-        payment_stream.pause(); // For example, set payment_stream.is_paused = true;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
+        .emit();
 
         Ok(())
     }
 
-    fn resume_stream(
+    fn terminate_stream(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
     ) -> ProgramResult {
-        // Check account permissions and state transitions
-
-        // Verify that the payment stream is owned by the program
+        // Check account permissions
         let account_info_iter = &mut accounts.iter();
         let payer_account = next_account_info(account_info_iter)?;
         let payment_stream_account = next_account_info(account_info_iter)?;
+        let recipient_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
 
+        // Verify that the payment stream is owned by the program
         if payment_stream_account.owner != program_id {
             return Err(ProgramError::IncorrectProgramId);
         }
 
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
         let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
         let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
 
@@ -258,46 +837,1203 @@ impl Processor {
             return Err(PaymentError::StreamAlreadyTerminated.into());
         }
 
-        if !payment_stream.is_paused {
-            return Err(PaymentError::StreamNotPaused.into());
+        if *payer_account.key != payment_stream.payer {
+            return Err(PaymentError::InvalidPayer.into());
         }
 
-        // Implement logic to resume the payment stream
-        payment_stream.resume(); // For example, set payment_stream.is_paused = false;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
+        if !payment_stream.is_recipient(recipient_account.key) {
+            return Err(PaymentError::InvalidRecipient.into());
+        }
 
-        Ok(())
+        // Either side may sign, as far as the stream's cancellation policy allows
+        let terminated_by = if payer_account.is_signer {
+            StreamParty::Payer
+        } else if recipient_account.is_signer {
+            StreamParty::Recipient
+        } else {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        };
+        if !payment_stream.cancellation_policy.allows(terminated_by) {
+            return Err(PaymentError::TerminationNotAllowed.into());
+        }
+
+        // One recipient of a split stream may not end the other recipients' streams
+        if terminated_by == StreamParty::Recipient && payment_stream.is_split() {
+            return Err(PaymentError::TerminationNotAllowed.into());
+        }
+
+        check_vault_address(
+            vault_account.key,
+            payment_stream_account.key,
+            payment_stream.vault_bump,
+            program_id,
+        )?;
+
+        // Vested funds belong to the recipient and the rest goes back to the payer,
+        // shifted by the penalty the terminating party owes the other
+        let current_time = current_timestamp()?;
+        // Every recipient of a split stream is settled before the payer is refunded
+        let recipient_amount = payment_stream.termination_payout(current_time, terminated_by)?;
+        let mut settled_amount: u64 = 0;
+        let mut fee: u64 = 0;
+        let mut payouts = Vec::new();
+        for (recipient, amount) in payment_stream.settle_recipients(recipient_amount)? {
+            let (payout_amount, recipient_fee) = Self::charge_withdrawal_fee(&mut payment_stream, &config, amount)?;
+            settled_amount = settled_amount.checked_add(amount).ok_or(PaymentError::ArithmeticOverflow)?;
+            fee = fee.checked_add(recipient_fee).ok_or(PaymentError::ArithmeticOverflow)?;
+            payouts.push((recipient, payout_amount));
+        }
+        payment_stream.terminate();
+        let terminated = |refund_amount| StreamEvent::Terminated {
+            payment_stream: *payment_stream_account.key,
+            terminated_by: match terminated_by {
+                StreamParty::Payer => *payer_account.key,
+                StreamParty::Recipient => *recipient_account.key,
+            },
+            recipient_amount: settled_amount,
+            refund_amount,
+            fee,
+        };
+
+        if payment_stream.is_spl_token {
+            let token_escrow = TokenEscrowAccounts::next(account_info_iter, &payment_stream, vault_account.key)?;
+            let recipient_token_account = match payment_stream.is_split() {
+                true => None,
+                false => Some(next_account_info(account_info_iter)?),
+            };
+            let payer_token_account = next_account_info(account_info_iter)?;
+            let treasury_token_account = next_account_info(account_info_iter)?;
+
+            check_token_account(payer_token_account, &payment_stream.mint, &payment_stream.payer)?;
+            check_token_account(treasury_token_account, &payment_stream.mint, &config.treasury)?;
+            let recipient_token_accounts = match recipient_token_account {
+                Some(recipient_token_account) => vec![recipient_token_account],
+                None => Self::next_split_accounts(account_info_iter, &payment_stream)?,
+            };
+
+            let mut destinations = Vec::with_capacity(payouts.len() + 2);
+            for ((recipient, payout_amount), recipient_token_account) in payouts.into_iter().zip(recipient_token_accounts) {
+                // Like a withdrawal, the payout goes to the recipient's payout destination once it sets one
+                match payment_stream.payout_destination {
+                    Some(payout_destination) => {
+                        if token_account_owner(recipient_token_account, &payment_stream.mint)? != payout_destination {
+                            return Err(PaymentError::InvalidPayoutDestination.into());
+                        }
+                    }
+                    None => check_token_account(recipient_token_account, &payment_stream.mint, &recipient)?,
+                }
+                payment_stream.transfer_fees_withheld = payment_stream
+                    .transfer_fees_withheld
+                    .checked_add(calculate_transfer_fee(token_escrow.mint, payout_amount)?)
+                    .ok_or(PaymentError::ArithmeticOverflow)?;
+                destinations.push((recipient_token_account, payout_amount));
+            }
+            payment_stream.pack(&mut payment_stream_data)?;
+
+            let refund_amount = token_balance(token_escrow.vault_token)?
+                .checked_sub(settled_amount)
+                .ok_or(PaymentError::InsufficientFunds)?;
+            destinations.push((treasury_token_account, fee));
+            destinations.push((payer_token_account, refund_amount));
+            terminated(refund_amount).emit();
+
+            for (destination_account, amount) in destinations {
+                Self::release_funds(
+                    &payment_stream,
+                    payment_stream_account.key,
+                    vault_account,
+                    system_program_account,
+                    Some(&token_escrow),
+                    destination_account,
+                    amount,
+                )?;
+            }
+
+            // The emptied vault token account's rent goes back to the payer
+            close_vault_token_account(
+                token_escrow.token_program,
+                token_escrow.vault_token,
+                token_escrow.mint,
+                payer_account,
+                vault_account,
+                payment_stream_account.key,
+                payment_stream.vault_bump,
+            )
+        } else {
+            let recipient_accounts = match (payment_stream.is_split(), payment_stream.payout_destination) {
+                (true, _) => Self::next_split_accounts(account_info_iter, &payment_stream)?,
+                (false, None) => vec![recipient_account],
+                // Like a withdrawal, the payout goes to the recipient's payout destination once it sets one
+                (false, Some(payout_destination)) => match next_account_info(account_info_iter) {
+                    Ok(destination) if *destination.key == payout_destination => vec![destination],
+                    _ => return Err(PaymentError::InvalidPayoutDestination.into()),
+                },
+            };
+            payment_stream.pack(&mut payment_stream_data)?;
+
+            // Emptying the vault also returns its rent-exempt reserve to the payer
+            let refund_amount = vault_account
+                .lamports()
+                .checked_sub(settled_amount)
+                .ok_or(PaymentError::InsufficientFunds)?;
+
+            let mut destinations: Vec<_> = recipient_accounts
+                .into_iter()
+                .zip(payouts)
+                .map(|(recipient_account, (_, payout_amount))| (recipient_account, payout_amount))
+                .collect();
+            destinations.push((treasury_account, fee));
+            destinations.push((payer_account, refund_amount));
+            terminated(refund_amount).emit();
+
+            for (destination_account, amount) in destinations {
+                Self::release_funds(
+                    &payment_stream,
+                    payment_stream_account.key,
+                    vault_account,
+                    system_program_account,
+                    None,
+                    destination_account,
+                    amount,
+                )?;
+            }
+
+            Ok(())
+        }
     }
 
-    fn current_timestamp() -> u64 {
-        // Implement logic to get the current timestamp using Solana's clock
-        // For example:
-        // solana_program::clock::get()?.unix_timestamp as u64
-        // Ensure that you handle any errors that might occur while getting the timestamp
-        0 // Change this to the actual implementation
+    /// Withdraws `amount`, or everything withdrawable up to the per-transaction
+    /// limit when `amount` is `None`. Cranked withdrawals are signed by a keeper
+    /// instead of the recipient.
+    fn withdraw(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        amount: Option<u64>,
+        is_crank: bool,
+    ) -> ProgramResult {
+        // Check account permissions
+        let account_info_iter = &mut accounts.iter();
+        let recipient_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+        let vault_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        // Verify that the payment stream is owned by the program
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        // A keeper can neither pick the amount nor send the payout anywhere but
+        // to the recipient's payout destination, and cannot crank a recipient more
+        // than once every `MIN_CRANK_INTERVAL`
+        let current_time = current_timestamp()?;
+        if is_crank {
+            if !payment_stream.allow_crank {
+                return Err(PaymentError::CrankNotAllowed.into());
+            }
+            if !payment_stream.is_recipient(recipient_account.key) {
+                return Err(PaymentError::InvalidRecipient.into());
+            }
+            let last_withdraw_time = payment_stream.last_withdraw_time_of(recipient_account.key);
+            if current_time < last_withdraw_time.saturating_add(MIN_CRANK_INTERVAL) {
+                return Err(PaymentError::CrankTooSoon.into());
+            }
+        } else {
+            Self::check_recipient(&payment_stream, recipient_account)?;
+        }
+
+        check_vault_address(
+            vault_account.key,
+            payment_stream_account.key,
+            payment_stream.vault_bump,
+            program_id,
+        )?;
+
+        // Each recipient of a split stream withdraws its own share
+        let withdrawable = payment_stream.withdrawable_by(recipient_account.key, current_time)?;
+        let amount = match amount {
+            Some(amount) if amount > config.max_withdrawal_amount => {
+                return Err(PaymentError::WithdrawalLimitExceeded.into());
+            }
+            Some(amount) => amount,
+            None if withdrawable == 0 => return Err(PaymentError::InsufficientFunds.into()),
+            None => withdrawable.min(config.max_withdrawal_amount),
+        };
+        if amount > withdrawable {
+            return Err(PaymentError::InsufficientFunds.into());
+        }
+
+        payment_stream.record_withdrawal_by(recipient_account.key, amount)?;
+        payment_stream.record_withdraw_time(recipient_account.key, current_time);
+        let (payout_amount, fee) = Self::charge_withdrawal_fee(&mut payment_stream, &config, amount)?;
+        let tip = match is_crank {
+            true => calculate_share(payout_amount, config.crank_tip_bps),
+            false => 0,
+        };
+        let payout_amount = payout_amount - tip;
+        let withdrawn = |destination: &Pubkey, keeper: Option<Pubkey>| StreamEvent::Withdrawn {
+            payment_stream: *payment_stream_account.key,
+            recipient: *recipient_account.key,
+            destination: *destination,
+            amount,
+            fee,
+            keeper,
+            tip,
+        };
+
+        // Release the funds from the vault, which only the program can sign for.
+        // Once the recipient sets a payout destination every payout goes there;
+        // until then a recipient that signed may name any destination.
+        if payment_stream.is_spl_token {
+            let token_escrow = TokenEscrowAccounts::next(account_info_iter, &payment_stream, vault_account.key)?;
+            let destination_token_account = next_account_info(account_info_iter)?;
+            let treasury_token_account = next_account_info(account_info_iter)?;
+            check_token_account(treasury_token_account, &payment_stream.mint, &config.treasury)?;
+
+            let destination_owner = token_account_owner(destination_token_account, &payment_stream.mint)?;
+            match payment_stream.payout_destination {
+                Some(payout_destination) if destination_owner != payout_destination => {
+                    return Err(PaymentError::InvalidPayoutDestination.into());
+                }
+                None if is_crank && destination_owner != *recipient_account.key => {
+                    return Err(PaymentError::InvalidTokenAccount.into());
+                }
+                _ => {}
+            }
+
+            let mut destinations = vec![(destination_token_account, payout_amount), (treasury_token_account, fee)];
+            let mut keeper = None;
+            if is_crank {
+                let keeper_account = Self::next_keeper_account(account_info_iter)?;
+                let keeper_token_account = next_account_info(account_info_iter)?;
+                check_token_account(keeper_token_account, &payment_stream.mint, keeper_account.key)?;
+                destinations.push((keeper_token_account, tip));
+                keeper = Some(*keeper_account.key);
+            }
+
+            // The recipient and any keeper receive their share less any Token-2022 transfer fee
+            for amount in [payout_amount, tip] {
+                payment_stream.transfer_fees_withheld = payment_stream
+                    .transfer_fees_withheld
+                    .checked_add(calculate_transfer_fee(token_escrow.mint, amount)?)
+                    .ok_or(PaymentError::ArithmeticOverflow)?;
+            }
+            payment_stream.pack(&mut payment_stream_data)?;
+            withdrawn(destination_token_account.key, keeper).emit();
+
+            for (destination_account, amount) in destinations {
+                Self::release_funds(
+                    &payment_stream,
+                    payment_stream_account.key,
+                    vault_account,
+                    system_program_account,
+                    Some(&token_escrow),
+                    destination_account,
+                    amount,
+                )?;
+            }
+
+            Ok(())
+        } else {
+            let mut destinations = vec![(recipient_account, payout_amount), (treasury_account, fee)];
+            let mut keeper = None;
+            if is_crank {
+                let keeper_account = Self::next_keeper_account(account_info_iter)?;
+                destinations.push((keeper_account, tip));
+                keeper = Some(*keeper_account.key);
+            }
+            match (payment_stream.payout_destination, next_account_info(account_info_iter)) {
+                (Some(payout_destination), Ok(destination)) if *destination.key == payout_destination => {
+                    destinations[0].0 = destination;
+                }
+                (Some(_), _) => return Err(PaymentError::InvalidPayoutDestination.into()),
+                (None, Ok(destination)) if !is_crank => destinations[0].0 = destination,
+                (None, _) => {}
+            }
+            payment_stream.pack(&mut payment_stream_data)?;
+            withdrawn(destinations[0].0.key, keeper).emit();
+
+            for (destination_account, amount) in destinations {
+                Self::release_funds(
+                    &payment_stream,
+                    payment_stream_account.key,
+                    vault_account,
+                    system_program_account,
+                    None,
+                    destination_account,
+                    amount,
+                )?;
+            }
+
+            Ok(())
+        }
     }
 
-    fn calculate_operational_fee(amount: u64) -> u64 {
-        // Implement logic to calculate operational fees based on the amount
-        // For example, you can use OPERATIONAL_FEE_RATE constant
-        // For example:
-        // (amount * OPERATIONAL_FEE_RATE) / 100
-        // Ensure that you handle any errors that might occur during the calculation
-        0 // Change this to the actual implementation
+    /// Reads the keeper that signed a cranked withdrawal.
+    fn next_keeper_account<'a, 'info>(
+        account_info_iter: &mut Iter<'a, AccountInfo<'info>>,
+    ) -> Result<&'a AccountInfo<'info>, ProgramError> {
+        let keeper_account = next_account_info(account_info_iter)?;
+        if !keeper_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        Ok(keeper_account)
     }
 
-    fn transfer_funds(
-        from_account: &AccountInfo,
-        to_account: &AccountInfo,
+    /// Pays `amount` out of the stream's escrow into `destination_account`, which is a
+    /// token account for token streams and any system account for lamport streams.
+    fn release_funds<'a>(
+        payment_stream: &PaymentStream,
+        payment_stream_key: &Pubkey,
+        vault_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        token_escrow: Option<&TokenEscrowAccounts<'_, 'a>>,
+        destination_account: &AccountInfo<'a>,
         amount: u64,
     ) -> ProgramResult {
-        // Implement logic to transfer funds between accounts
-        // You can use the solana_program::program::invoke function for token transfers
-        // For example:
-        // invoke(
-        //     &transfer(&from_account.key, &to_account.key, amount),
-        //     &[from_account.clone(), to_account.clone()],
-        // )?;
-        Ok(())
+        if amount == 0 {
+            return Ok(());
+        }
+
+        match token_escrow {
+            Some(token_escrow) => transfer_tokens_from_vault(
+                token_escrow.token_program,
+                token_escrow.vault_token,
+                token_escrow.mint,
+                destination_account,
+                vault_account,
+                payment_stream_key,
+                payment_stream.vault_bump,
+                amount,
+                unpack_mint(token_escrow.mint)?.decimals,
+            ),
+            None => transfer_from_vault(
+                vault_account,
+                destination_account,
+                system_program_account,
+                payment_stream_key,
+                payment_stream.vault_bump,
+                amount,
+            ),
+        }
+    }
+
+    fn pause_stream(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        // Check account permissions and state transitions
+
+        // Verify that the payment stream is owned by the program
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        Self::check_payer_or_delegate(&payment_stream, authority_account)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        if payment_stream.is_terminated {
+            return Err(PaymentError::StreamAlreadyTerminated.into());
+        }
+
+        if payment_stream.is_paused {
+            return Err(PaymentError::StreamAlreadyPaused.into());
+        }
+
+        // Vesting stops here, but what already vested stays withdrawable
+        payment_stream.pause(current_timestamp()?);
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::Paused {
+            payment_stream: *payment_stream_account.key,
+            paused_at: payment_stream.paused_at,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn resume_stream(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        // Check account permissions and state transitions
+
+        // Verify that the payment stream is owned by the program
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+        Self::check_payer_or_delegate(&payment_stream, authority_account)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        if payment_stream.is_terminated {
+            return Err(PaymentError::StreamAlreadyTerminated.into());
+        }
+
+        if !payment_stream.is_paused {
+            return Err(PaymentError::StreamNotPaused.into());
+        }
+
+        // The time spent paused never vests
+        let current_time = current_timestamp()?;
+        payment_stream.resume(current_time);
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::Resumed {
+            payment_stream: *payment_stream_account.key,
+            resumed_at: current_time,
+            total_paused_duration: payment_stream.total_paused_duration,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn transfer_recipient(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let authority_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+        let new_recipient_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+
+        // The payer signs to correct the recipient, otherwise the recipient hands it over
+        let transferred_by = Self::check_party(&payment_stream, authority_account)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        if payment_stream.is_terminated {
+            return Err(PaymentError::StreamAlreadyTerminated.into());
+        }
+
+        if !payment_stream.is_transferable_by(transferred_by) {
+            return Err(PaymentError::TransferNotAllowed.into());
+        }
+
+        // A recipient hands over its own share; the payer of a split stream names
+        // the recipient being replaced after the config account
+        let old_recipient = match transferred_by {
+            StreamParty::Recipient => *authority_account.key,
+            StreamParty::Payer if payment_stream.is_split() => {
+                next_account_info(account_info_iter)?;
+                *next_account_info(account_info_iter)?.key
+            }
+            StreamParty::Payer => payment_stream.recipient,
+        };
+
+        // Vesting and withdrawals are tracked per recipient, so whatever has vested
+        // but not been withdrawn moves to the new recipient with it
+        payment_stream.replace_recipient(&old_recipient, new_recipient_account.key)?;
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::RecipientTransferred {
+            payment_stream: *payment_stream_account.key,
+            transferred_by: *authority_account.key,
+            old_recipient,
+            new_recipient: *new_recipient_account.key,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Points the recipient's withdrawals, cranked or not, at a destination of its
+    /// choosing. Naming the recipient itself clears it.
+    fn set_payout_destination(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let recipient_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+        let destination_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
+        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
+
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        if payment_stream.is_terminated {
+            return Err(PaymentError::StreamAlreadyTerminated.into());
+        }
+
+        if payment_stream.is_split() {
+            return Err(PaymentError::PayoutDestinationNotSupported.into());
+        }
+
+        Self::check_recipient(&payment_stream, recipient_account)?;
+
+        payment_stream.payout_destination =
+            (destination_account.key != recipient_account.key).then_some(*destination_account.key);
+        payment_stream.pack(&mut payment_stream_data)?;
+        StreamEvent::PayoutDestinationSet {
+            payment_stream: *payment_stream_account.key,
+            recipient: *recipient_account.key,
+            payout_destination: payment_stream.payout_destination,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Rewrites a stream stored under an earlier layout version in the current one.
+    fn migrate_stream(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payer_account = next_account_info(account_info_iter)?;
+        let payment_stream_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        if !payer_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
+
+        let (payment_stream, version) = PaymentStream::unpack_any_version(&payment_stream_account.try_borrow_data()?)?;
+        if version == PaymentStream::VERSION {
+            return Ok(());
+        }
+
+        // New fields normally fit in the reserved space. Only a layout that outgrows
+        // it needs a larger account, whose extra rent the payer covers first.
+        if payment_stream_account.data_len() < PaymentStream::LEN {
+            let rent_due = Rent::get()?
+                .minimum_balance(PaymentStream::LEN)
+                .saturating_sub(payment_stream_account.lamports());
+            if rent_due > 0 {
+                Self::transfer_funds(payer_account, payment_stream_account, system_program_account, rent_due)?;
+            }
+            payment_stream_account.realloc(PaymentStream::LEN, false)?;
+        }
+
+        payment_stream.pack(&mut payment_stream_account.try_borrow_mut_data()?)?;
+        StreamEvent::Migrated {
+            payment_stream: *payment_stream_account.key,
+            from_version: version,
+            to_version: PaymentStream::VERSION,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn initialize_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: ConfigParams,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+        let program_data_account = next_account_info(account_info_iter)?;
+
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        // Only the program's upgrade authority may claim the admin role, so nobody
+        // can front-run the deployer between deployment and initialization
+        Self::check_upgrade_authority(program_id, program_data_account, admin_account.key)?;
+
+        let (config_address, config_bump) = find_config_address(program_id);
+        if *config_account.key != config_address {
+            return Err(PaymentError::InvalidConfigAccount.into());
+        }
+
+        if config_account.owner == program_id {
+            return Err(PaymentError::ConfigAlreadyInitialized.into());
+        }
+
+        let (treasury_address, treasury_bump) = find_treasury_address(program_id);
+        if *treasury_account.key != treasury_address {
+            return Err(PaymentError::InvalidTreasuryAccount.into());
+        }
+
+        let mut config = ProgramConfig {
+            is_initialized: true,
+            admin: *admin_account.key,
+            treasury: treasury_address,
+            treasury_bump,
+            ..ProgramConfig::default()
+        };
+        config.set_params(&params)?;
+
+        create_pda_account(
+            admin_account,
+            config_account,
+            system_program_account,
+            ProgramConfig::LEN,
+            program_id,
+            &[CONFIG_SEED, &[config_bump]],
+        )?;
+
+        config.pack(&mut config_account.try_borrow_mut_data()?)?;
+
+        // Like stream vaults, the treasury is a plain system account that needs a
+        // rent-exempt reserve before it can hold small fees
+        let rent_reserve = Rent::get()?
+            .minimum_balance(0)
+            .saturating_sub(treasury_account.lamports());
+        if rent_reserve > 0 {
+            Self::transfer_funds(admin_account, treasury_account, system_program_account, rent_reserve)?;
+        }
+        StreamEvent::ConfigUpdated {
+            admin: *admin_account.key,
+            params,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    fn update_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: ConfigParams,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::load_config(program_id, config_account)?;
+        Self::check_admin(&config, admin_account)?;
+
+        config.set_params(&params)?;
+        config.pack(&mut config_account.try_borrow_mut_data()?)?;
+        StreamEvent::ConfigUpdated {
+            admin: *admin_account.key,
+            params,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Flips the global circuit breaker checked by `check_program_not_paused`.
+    fn set_program_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        is_paused: bool,
+        allow_vested_withdrawals: bool,
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::load_config(program_id, config_account)?;
+        Self::check_admin(&config, admin_account)?;
+
+        config.is_paused = is_paused;
+        config.allow_vested_withdrawals = allow_vested_withdrawals;
+        config.pack(&mut config_account.try_borrow_mut_data()?)?;
+        StreamEvent::ProgramPauseSet {
+            admin: *admin_account.key,
+            is_paused,
+            allow_vested_withdrawals,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Hands the admin role to a new key. The new admin co-signs so the role
+    /// cannot be sent to a key nobody controls.
+    fn transfer_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let admin_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let new_admin_account = next_account_info(account_info_iter)?;
+
+        let mut config = Self::load_config(program_id, config_account)?;
+        Self::check_admin(&config, admin_account)?;
+
+        if !new_admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        config.admin = *new_admin_account.key;
+        config.pack(&mut config_account.try_borrow_mut_data()?)?;
+        StreamEvent::AdminTransferred {
+            old_admin: *admin_account.key,
+            new_admin: *new_admin_account.key,
+        }
+        .emit();
+
+        Ok(())
+    }
+
+    /// Sweeps every fee held by the treasury, in lamports or in one token mint, to
+    /// `destination_account`.
+    fn collect_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let fee_authority_account = next_account_info(account_info_iter)?;
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+        let destination_account = next_account_info(account_info_iter)?;
+        let system_program_account = next_account_info(account_info_iter)?;
+
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
+        if !fee_authority_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *fee_authority_account.key != config.fee_authority {
+            return Err(PaymentError::InvalidFeeAuthority.into());
+        }
+
+        let fee_collected = |mint: Option<&Pubkey>, amount| StreamEvent::FeeCollected {
+            fee_authority: *fee_authority_account.key,
+            destination: *destination_account.key,
+            mint: mint.copied(),
+            amount,
+        };
+
+        // Token fees pass the mint, the treasury token account and the token program
+        match next_account_info(account_info_iter).ok() {
+            Some(mint_account) => {
+                let treasury_token_account = next_account_info(account_info_iter)?;
+                let token_program_account = next_account_info(account_info_iter)?;
+
+                check_token_program(token_program_account.key)?;
+                check_token_account(treasury_token_account, mint_account.key, &config.treasury)?;
+
+                let amount = Self::treasury_balance(treasury_account, Some(treasury_token_account))?;
+                if amount == 0 {
+                    return Ok(());
+                }
+                fee_collected(Some(mint_account.key), amount).emit();
+
+                transfer_tokens_from_treasury(
+                    token_program_account,
+                    treasury_token_account,
+                    mint_account,
+                    destination_account,
+                    treasury_account,
+                    config.treasury_bump,
+                    amount,
+                    unpack_mint(mint_account)?.decimals,
+                )
+            }
+            None => {
+                let amount = Self::treasury_balance(treasury_account, None)?;
+                if amount == 0 {
+                    return Ok(());
+                }
+                fee_collected(None, amount).emit();
+
+                transfer_from_treasury(
+                    treasury_account,
+                    destination_account,
+                    system_program_account,
+                    config.treasury_bump,
+                    amount,
+                )
+            }
+        }
+    }
+
+    /// Reports the stream's status and balances at the current time through the
+    /// instruction's return data, as a Borsh-serialized `StreamSnapshot`.
+    fn query_stream(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let payment_stream_account = next_account_info(account_info_iter)?;
+
+        if payment_stream_account.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let payment_stream = PaymentStream::unpack(&payment_stream_account.try_borrow_data()?)?;
+        if !payment_stream.is_initialized {
+            return Err(PaymentError::StreamNotInitialized.into());
+        }
+
+        let snapshot = payment_stream.snapshot(current_timestamp()?)?;
+        set_return_data(&snapshot.try_to_vec()?);
+
+        Ok(())
+    }
+
+    /// Reports the collectable treasury balance through the instruction's return data.
+    fn query_treasury(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let config_account = next_account_info(account_info_iter)?;
+        let treasury_account = next_account_info(account_info_iter)?;
+
+        let config = Self::load_fee_config(program_id, config_account, treasury_account)?;
+
+        // Token balances pass the mint and the treasury token account
+        let treasury_token_account = match next_account_info(account_info_iter).ok() {
+            Some(mint_account) => {
+                let treasury_token_account = next_account_info(account_info_iter)?;
+                check_token_account(treasury_token_account, mint_account.key, &config.treasury)?;
+                Some(treasury_token_account)
+            }
+            None => None,
+        };
+
+        let balance = Self::treasury_balance(treasury_account, treasury_token_account)?;
+        set_return_data(&balance.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Splits a payout of `amount` into what the recipient receives and the fee owed
+    /// to the treasury, recording the fee on the stream.
+    fn charge_withdrawal_fee(
+        payment_stream: &mut PaymentStream,
+        config: &ProgramConfig,
+        amount: u64,
+    ) -> Result<(u64, u64), ProgramError> {
+        if !config.fee_charge_point.charges_withdrawal() {
+            return Ok((amount, 0));
+        }
+
+        let fee = calculate_fee(amount, config.fee_bps)?;
+        payment_stream.fees_paid = payment_stream
+            .fees_paid
+            .checked_add(fee)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+
+        Ok((amount - fee, fee))
+    }
+
+    /// Rejects state-changing instructions while the program is paused. Queries,
+    /// config administration and layout migrations always go through, and
+    /// withdrawals of vested funds go through when the config allows them.
+    /// Instructions that can be frozen find the config PDA anywhere in their
+    /// account list.
+    fn check_program_not_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        instruction: &StreamPayInstruction,
+    ) -> ProgramResult {
+        let can_be_frozen = match instruction {
+            StreamPayInstruction::InitializeStream { .. }
+            | StreamPayInstruction::BatchInitializeStreams { .. }
+            | StreamPayInstruction::UpdateStream { .. }
+            | StreamPayInstruction::TerminateStream
+            | StreamPayInstruction::Withdraw { .. }
+            | StreamPayInstruction::WithdrawMax
+            | StreamPayInstruction::CrankWithdraw
+            | StreamPayInstruction::PauseStream
+            | StreamPayInstruction::ResumeStream
+            | StreamPayInstruction::TransferRecipient
+            | StreamPayInstruction::TopUp { .. }
+            | StreamPayInstruction::RequireUpdateConsent
+            | StreamPayInstruction::UpdateBlackoutWindows { .. }
+            | StreamPayInstruction::SetPayoutDestination
+            | StreamPayInstruction::CollectFees => true,
+            StreamPayInstruction::QueryStream
+            | StreamPayInstruction::QueryTreasury
+            | StreamPayInstruction::InitializeConfig { .. }
+            | StreamPayInstruction::UpdateConfig { .. }
+            | StreamPayInstruction::TransferAdmin
+            | StreamPayInstruction::SetProgramPaused { .. }
+            | StreamPayInstruction::MigrateStream => false,
+        };
+        if !can_be_frozen {
+            return Ok(());
+        }
+
+        let config_address = find_config_address(program_id).0;
+        let config_account = accounts
+            .iter()
+            .find(|account| *account.key == config_address)
+            .ok_or(PaymentError::InvalidConfigAccount)?;
+        let config = Self::load_config(program_id, config_account)?;
+
+        let is_vested_withdrawal = matches!(
+            instruction,
+            StreamPayInstruction::Withdraw { .. }
+                | StreamPayInstruction::WithdrawMax
+                | StreamPayInstruction::CrankWithdraw
+        );
+        if config.is_paused && !(is_vested_withdrawal && config.allow_vested_withdrawals) {
+            return Err(PaymentError::ProgramPaused.into());
+        }
+
+        Ok(())
+    }
+
+    /// Reads the mint account of a token stream, which must be `mint`. Lamport
+    /// streams, with no `mint`, read nothing.
+    fn next_mint_account<'a, 'info>(
+        account_info_iter: &mut Iter<'a, AccountInfo<'info>>,
+        mint: Option<&Pubkey>,
+    ) -> Result<Option<&'a AccountInfo<'info>>, ProgramError> {
+        let Some(mint) = mint else {
+            return Ok(None);
+        };
+
+        let mint_account = next_account_info(account_info_iter)?;
+        if mint_account.key != mint {
+            return Err(PaymentError::InvalidMint.into());
+        }
+        Ok(Some(mint_account))
+    }
+
+    /// Checks that the stream's recipient, or one of the recipients of a split
+    /// stream, signed the instruction.
+    fn check_recipient(payment_stream: &PaymentStream, recipient_account: &AccountInfo) -> ProgramResult {
+        if !payment_stream.is_recipient(recipient_account.key) {
+            return Err(PaymentError::InvalidRecipient.into());
+        }
+
+        if !recipient_account.is_signer {
+            return Err(PaymentError::RecipientNotSigner.into());
+        }
+
+        Ok(())
+    }
+
+    /// Reads one destination account per recipient of a split stream, in split
+    /// order: the recipient's wallet for lamport streams, or its token account
+    /// for token streams, which the caller checks.
+    fn next_split_accounts<'a, 'info>(
+        account_info_iter: &mut Iter<'a, AccountInfo<'info>>,
+        payment_stream: &PaymentStream,
+    ) -> Result<Vec<&'a AccountInfo<'info>>, ProgramError> {
+        payment_stream
+            .splits
+            .iter()
+            .map(|split| {
+                let account = next_account_info(account_info_iter)?;
+                if !payment_stream.is_spl_token && *account.key != split.recipient {
+                    return Err(PaymentError::InvalidRecipient.into());
+                }
+                Ok(account)
+            })
+            .collect()
+    }
+
+    /// Checks that the recipient, or every recipient of a split stream, co-signed a
+    /// change to the stream's terms, when the stream requires their consent. The
+    /// recipients follow the config PDA, in split order.
+    fn check_update_consent(
+        program_id: &Pubkey,
+        payment_stream: &PaymentStream,
+        account_info_iter: &mut Iter<AccountInfo>,
+    ) -> ProgramResult {
+        if !payment_stream.update_requires_recipient_consent {
+            return Ok(());
+        }
+
+        let config_account = next_account_info(account_info_iter)?;
+        if *config_account.key != find_config_address(program_id).0 {
+            return Err(PaymentError::InvalidConfigAccount.into());
+        }
+
+        // One recipient of a split stream may not agree to new terms for the others
+        let recipients: Vec<_> = match payment_stream.is_split() {
+            true => payment_stream.splits.iter().map(|split| split.recipient).collect(),
+            false => vec![payment_stream.recipient],
+        };
+        for recipient in recipients {
+            let recipient_account = next_account_info(account_info_iter)?;
+            if *recipient_account.key != recipient {
+                return Err(PaymentError::InvalidRecipient.into());
+            }
+            if !recipient_account.is_signer {
+                return Err(PaymentError::RecipientNotSigner.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that either the stream's payer or its recipient signed the
+    /// instruction, returning which one did.
+    fn check_party(
+        payment_stream: &PaymentStream,
+        authority_account: &AccountInfo,
+    ) -> Result<StreamParty, ProgramError> {
+        if *authority_account.key != payment_stream.payer {
+            Self::check_recipient(payment_stream, authority_account)?;
+            return Ok(StreamParty::Recipient);
+        }
+
+        if !authority_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
+
+        Ok(StreamParty::Payer)
+    }
+
+    /// Checks that the stream's payer, or its delegate acting for the payer, signed the instruction.
+    fn check_payer_or_delegate(payment_stream: &PaymentStream, authority_account: &AccountInfo) -> ProgramResult {
+        let is_delegate = payment_stream.delegate == Some(*authority_account.key);
+        if *authority_account.key != payment_stream.payer && !is_delegate {
+            return Err(PaymentError::InvalidAuthority.into());
+        }
+
+        if !authority_account.is_signer {
+            return Err(PaymentError::AuthorityNotSigner.into());
+        }
+
+        Ok(())
+    }
+
+    /// Reads the program config, checking the config account against its PDA.
+    fn load_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+    ) -> Result<ProgramConfig, ProgramError> {
+        if config_account.owner != program_id || *config_account.key != find_config_address(program_id).0 {
+            return Err(PaymentError::InvalidConfigAccount.into());
+        }
+
+        let config = ProgramConfig::unpack(&config_account.try_borrow_data()?)?;
+        if !config.is_initialized {
+            return Err(PaymentError::InvalidConfigAccount.into());
+        }
+
+        Ok(config)
+    }
+
+    /// Reads the program config for an instruction that moves fees, also checking
+    /// the treasury account against it.
+    fn load_fee_config(
+        program_id: &Pubkey,
+        config_account: &AccountInfo,
+        treasury_account: &AccountInfo,
+    ) -> Result<ProgramConfig, ProgramError> {
+        let config = Self::load_config(program_id, config_account)?;
+
+        if *treasury_account.key != config.treasury {
+            return Err(PaymentError::InvalidTreasuryAccount.into());
+        }
+
+        Ok(config)
+    }
+
+    /// Checks that `authority` is the upgrade authority recorded in the program's
+    /// ProgramData account.
+    fn check_upgrade_authority(
+        program_id: &Pubkey,
+        program_data_account: &AccountInfo,
+        authority: &Pubkey,
+    ) -> ProgramResult {
+        let (program_data_address, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if *program_data_account.key != program_data_address
+            || *program_data_account.owner != bpf_loader_upgradeable::id()
+        {
+            return Err(PaymentError::InvalidUpgradeAuthority.into());
+        }
+
+        match bincode::deserialize(&program_data_account.try_borrow_data()?) {
+            Ok(UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address: Some(upgrade_authority),
+                ..
+            }) if upgrade_authority == *authority => Ok(()),
+            _ => Err(PaymentError::InvalidUpgradeAuthority.into()),
+        }
+    }
+
+    /// Checks that `admin_account` is the config admin and signed the instruction.
+    fn check_admin(config: &ProgramConfig, admin_account: &AccountInfo) -> ProgramResult {
+        if !admin_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if *admin_account.key != config.admin {
+            return Err(PaymentError::InvalidAdmin.into());
+        }
+
+        Ok(())
+    }
+
+    /// Returns what `CollectFees` would sweep: the treasury token account's balance for
+    /// tokens, or the treasury's lamports above its rent-exempt reserve.
+    fn treasury_balance(
+        treasury_account: &AccountInfo,
+        treasury_token_account: Option<&AccountInfo>,
+    ) -> Result<u64, ProgramError> {
+        match treasury_token_account {
+            Some(treasury_token_account) => token_balance(treasury_token_account),
+            None => Ok(treasury_account
+                .lamports()
+                .saturating_sub(Rent::get()?.minimum_balance(0))),
+        }
+    }
+
+    fn transfer_funds<'a>(
+        from_account: &AccountInfo<'a>,
+        to_account: &AccountInfo<'a>,
+        system_program_account: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        invoke(
+            &system_instruction::transfer(from_account.key, to_account.key, amount),
+            &[
+                from_account.clone(),
+                to_account.clone(),
+                system_program_account.clone(),
+            ],
+        )
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,128 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use crate::constants::{BPS_DENOMINATOR, MAX_CRANK_TIP_BPS, MINIMUM_AMOUNT_LAMPORTS};
+use crate::error::PaymentError;
+
+pub use crate::payment_stream::{
+    BlackoutWindow, Breakpoint, CancellationPolicy, PaymentStream, ReleaseCurve, SplitRecipient, StreamParty,
+    StreamSnapshot, StreamStatus,
+};
+
+/// Where in a stream's lifecycle the operational fee is taken.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeeChargePoint {
+    /// Charged on top of the deposit, paid by the payer.
+    #[default]
+    Deposit,
+    /// Deducted from every payout, paid by the recipient.
+    Withdrawal,
+    /// Charged on the deposit and again on every payout.
+    Both,
+}
+
+impl FeeChargePoint {
+    pub fn charges_deposit(self) -> bool {
+        matches!(self, Self::Deposit | Self::Both)
+    }
+
+    pub fn charges_withdrawal(self) -> bool {
+        matches!(self, Self::Withdrawal | Self::Both)
+    }
+}
+
+/// Admin-controlled limits and fee settings, passed to `InitializeConfig` and
+/// `UpdateConfig`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConfigParams {
+    pub minimum_deposit: u64,
+    pub max_withdrawal_amount: u64, // Largest amount a single `Withdraw` can pay out
+    pub fee_bps: u16,
+    pub fee_charge_point: FeeChargePoint,
+    pub fee_authority: Pubkey,
+    pub crank_tip_bps: u16, // Share of a cranked withdrawal paid to the keeper
+}
+
+/// Program-wide settings, stored in a single PDA derived from `CONFIG_SEED`.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Debug, Default, PartialEq)]
+pub struct ProgramConfig {
+    pub is_initialized: bool,
+    pub admin: Pubkey, // Only signer allowed to change the config
+    pub fee_authority: Pubkey, // Only signer allowed to collect fees from the treasury
+    pub treasury: Pubkey, // PDA that receives every operational fee
+    pub treasury_bump: u8,
+    pub minimum_deposit: u64,
+    pub max_withdrawal_amount: u64,
+    pub fee_bps: u16,
+    pub fee_charge_point: FeeChargePoint,
+    pub is_paused: bool, // Freezes every state-changing instruction except config administration
+    pub allow_vested_withdrawals: bool, // Lets recipients keep withdrawing vested funds while paused
+    pub crank_tip_bps: u16,
+}
+
+impl ProgramConfig {
+    /// Tags the config account, telling it apart from the program's other accounts.
+    pub const DISCRIMINATOR: [u8; 8] = *b"pconfig:";
+    /// Layout version of the account, bumped whenever the encoded fields change.
+    pub const VERSION: u8 = 1;
+    /// Bytes in front of the encoded config: the discriminator and the version.
+    pub const HEADER_LEN: usize = 8 + 1;
+    /// Encoded size of the config.
+    pub const DATA_LEN: usize = 1 + 32 + 32 + 32 + 1 + 8 + 8 + 2 + 1 + 1 + 1 + 2;
+    /// Zeroed space at the end of the account, left free for later use. Fields
+    /// added by later layout versions are taken out of it, so `LEN` stays the same.
+    pub const RESERVED_LEN: usize = 64;
+    /// Size of the config account.
+    pub const LEN: usize = Self::HEADER_LEN + Self::DATA_LEN + Self::RESERVED_LEN;
+
+    /// Writes the discriminator, the version and the encoded config, zeroing the
+    /// rest of the account.
+    pub fn pack(&self, dst: &mut [u8]) -> Result<(), ProgramError> {
+        let encoded = self.try_to_vec().map_err(|_| ProgramError::InvalidAccountData)?;
+        let dst = dst.get_mut(..Self::LEN).ok_or(ProgramError::AccountDataTooSmall)?;
+        let (header, data) = dst.split_at_mut(Self::HEADER_LEN);
+        header[..8].copy_from_slice(&Self::DISCRIMINATOR);
+        header[8] = Self::VERSION;
+        data.get_mut(..encoded.len())
+            .ok_or(ProgramError::AccountDataTooSmall)?
+            .copy_from_slice(&encoded);
+        data[encoded.len()..].fill(0);
+        Ok(())
+    }
+
+    /// Reads a config written by `pack`, rejecting other accounts and other
+    /// layout versions.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::HEADER_LEN || input[..8] != Self::DISCRIMINATOR {
+            return Err(PaymentError::InvalidAccountType.into());
+        }
+        if input[8] != Self::VERSION {
+            return Err(PaymentError::UnsupportedAccountVersion.into());
+        }
+
+        // The encoded config is followed by zeroed space, so ignore trailing bytes
+        Self::deserialize(&mut &input[Self::HEADER_LEN..]).map_err(|_| ProgramError::InvalidAccountData)
+    }
+
+    /// Validates `params` and applies them to the config.
+    pub fn set_params(&mut self, params: &ConfigParams) -> Result<(), ProgramError> {
+        if params.minimum_deposit < MINIMUM_AMOUNT_LAMPORTS || params.max_withdrawal_amount == 0 {
+            return Err(PaymentError::InvalidAmount.into());
+        }
+
+        if u64::from(params.fee_bps) > BPS_DENOMINATOR {
+            return Err(PaymentError::InvalidFeeRate.into());
+        }
+
+        if params.crank_tip_bps > MAX_CRANK_TIP_BPS {
+            return Err(PaymentError::InvalidCrankTip.into());
+        }
+
+        self.minimum_deposit = params.minimum_deposit;
+        self.max_withdrawal_amount = params.max_withdrawal_amount;
+        self.fee_bps = params.fee_bps;
+        self.fee_charge_point = params.fee_charge_point;
+        self.fee_authority = params.fee_authority;
+        self.crank_tip_bps = params.crank_tip_bps;
+        Ok(())
+    }
+}
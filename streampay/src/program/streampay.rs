@@ -1,296 +0,0 @@
-use solana_program::{
-    account_info::{next_account_info, AccountInfo},
-    entrypoint::ProgramResult,
-    msg,
-    program::invoke,
-    program_error::ProgramError,
-    pubkey::Pubkey,
-    program_pack::{Pack, Sealed},
-    sysvar::rent::Rent,
-};
-use crate::error::PaymentError;
-use crate::constants::{MINIMUM_AMOUNT, OPERATIONAL_FEE_RATE, MAX_WITHDRAWAL_AMOUNT};
-
-/// Represents the main program structure.
-pub struct StreamPay;
-
-/// Enum that defines the various instructions or operations that the program can handle.
-pub enum StreamPayInstruction {
-    InitPaymentStream {
-        start_time: u64,
-        interval: u64,
-        amount_per_interval: u64,
-    },
-    UpdatePaymentStream {
-        interval: Option<u64>,
-        amount_per_interval: Option<u64>,
-    },
-    TerminatePaymentStream,
-    Withdraw { amount: u64 },
-    Pause,
-    Resume,
-    QueryStreamDetails,
-}
-
-/// Represents the structure of a payment stream with its properties.
-pub struct PaymentStream {
-    start_time: u64,
-    interval: u64,
-    amount_per_interval: u64,
-    withdrawn_amount: u64,
-    last_withdraw_time: u64,
-    is_paused: bool,
-    is_terminated: bool,
-}
-
-impl Sealed for PaymentStream {}
-
-impl Pack for PaymentStream {
-    const LEN: usize = 57; // Adjust based on the actual size
-
-    fn pack_into_slice(&self, output: &mut [u8]) {
-        // Implement packing logic
-    }
-
-    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
-        // Implement unpacking logic
-    }
-}
-
-impl StreamPay {
-    /// Main entry point for processing instructions.
-    pub fn process(instruction: StreamPayInstruction, accounts: &[AccountInfo]) -> ProgramResult {
-        match instruction {
-            InitPaymentStream { start_time, interval, amount_per_interval } => {
-                Self::initialize_payment_stream(accounts, start_time, interval, amount_per_interval)
-            },
-            UpdatePaymentStream { interval, amount_per_interval } => {
-                Self::update_payment_stream(accounts, interval, amount_per_interval)
-            },
-            TerminatePaymentStream => Self::terminate_payment_stream(accounts),
-            Withdraw { amount } => Self::withdraw_from_stream(accounts, amount),
-            Pause => Self::pause_payment_stream(accounts),
-            Resume => Self::resume_payment_stream(accounts),
-            QueryStreamDetails => Self::query_stream_details(accounts),
-        }
-    }
-
-    fn initialize_payment_stream(
-        accounts: &[AccountInfo],
-        start_time: u64,
-        interval: u64,
-        amount_per_interval: u64,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-        let payer_account = next_account_info(account_info_iter)?;
-
-        if payment_stream_account.owner != payer_account.key {
-            return Err(ProgramError::IncorrectProgramId);
-        }
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let payment_stream = PaymentStream {
-            start_time,
-            interval,
-            amount_per_interval,
-            withdrawn_amount: 0,
-            last_withdraw_time: start_time,
-            is_paused: false,
-            is_terminated: false,
-        };
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn update_payment_stream(
-        accounts: &[AccountInfo],
-        interval: Option<u64>,
-        amount_per_interval: Option<u64>,
-    ) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        if let Some(new_interval) = interval {
-            payment_stream.interval = new_interval;
-        }
-        if let Some(new_amount) = amount_per_interval {
-            payment_stream.amount_per_interval = new_amount;
-        }
-
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn terminate_payment_stream(accounts: &[AccountInfo]) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        if payment_stream.is_terminated {
-            return Err(PaymentError::StreamAlreadyTerminated.into());
-        }
-
-        payment_stream.is_terminated = true;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn withdraw_from_stream(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-        let recipient_account = next_account_info(account_info_iter)?;
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        let current_time = current_timestamp(); // Assuming a function to get the current timestamp
-        let elapsed_time = current_time - payment_stream.last_withdraw_time;
-        let max_withdrawable = (elapsed_time / payment_stream.interval) * payment_stream.amount_per_interval;
-
-        if amount > max_withdrawable {
-            return Err(PaymentError::InsufficientFunds.into());
-        }
-
-        payment_stream.withdrawn_amount += amount;
-        payment_stream.last_withdraw_time = current_time;
-
-        // Transfer the funds to the recipient
-        // Assuming a function `transfer_funds` exists to handle the actual transfer
-        transfer_funds(payment_stream_account, recipient_account, amount)?;
-
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn pause_payment_stream(accounts: &[AccountInfo]) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        if payment_stream.is_paused {
-            return Err(PaymentError::StreamAlreadyPaused.into());
-        }
-
-        if payment_stream.is_terminated {
-            return Err(PaymentError::StreamAlreadyTerminated.into());
-        }
-
-        payment_stream.is_paused = true;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn resume_payment_stream(accounts: &[AccountInfo]) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-
-        let mut payment_stream_data = payment_stream_account.try_borrow_mut_data()?;
-        let mut payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        if !payment_stream.is_paused {
-            return Err(PaymentError::StreamNotPaused.into());
-        }
-
-        payment_stream.is_paused = false;
-        PaymentStream::pack(payment_stream, &mut payment_stream_data);
-
-        Ok(())
-    }
-
-    fn query_stream_details(accounts: &[AccountInfo]) -> ProgramResult {
-        let account_info_iter = &mut accounts.iter();
-        let payment_stream_account = next_account_info(account_info_iter)?;
-
-        let payment_stream_data = payment_stream_account.try_borrow_data()?;
-        let payment_stream = PaymentStream::unpack(&payment_stream_data)?;
-
-        msg!("Payment Stream Details: {:?}", payment_stream);
-
-        Ok(())
-    }
-}
-
-// Helper function to transfer funds
-fn transfer_funds(
-    from_account: &AccountInfo,
-    to_account: &AccountInfo,
-    amount: u64,
-) -> ProgramResult {
-    // Create a transfer instruction
-    let transfer_instruction = solana_program::system_instruction::transfer(
-        from_account.key,
-        to_account.key,
-        amount,
-    );
-
-    // Invoke the transfer instruction
-    invoke(
-        &transfer_instruction,
-        &[from_account.clone(), to_account.clone()],
-    )
-}
-
-// Helper function to get the current timestamp
-fn current_timestamp() -> u64 {
-    // Placeholder logic to get the current timestamp
-    // This can be implemented using the Solana SDK's provided methods
-    0
-}
-
-// ... [tests]
-
-#[cfg(test)]
-mod tests {
-
-}
-    #[tokio::test]
-    async fn test_withdraw_from_stream_error() {
-        // Setup
-        let mut payment_stream = PaymentStream::new(100, 10, 5);
-        payment_stream.withdrawn_amount = 5;  // Simulate that 5 lamports have already been withdrawn
-        
-        // Simulate a withdrawal of 6 lamports from the stream
-        // Here, we'd normally call a function like `withdraw_from_stream`
-        // but since it's omitted in the updated code, we can simulate an error
-        let result = if payment_stream.withdrawn_amount + 6 > payment_stream.amount_per_interval {
-            Err(PaymentError::InsufficientFunds)
-        } else {
-            Ok(())
-        };
-
-        // Assert
-        assert_eq!(result, Err(PaymentError::InsufficientFunds));
-    }
-
-    #[tokio::test]
-    async fn test_terminate_stream_already_terminated() {
-        // Setup
-        let mut payment_stream = PaymentStream::new(100, 10, 5);
-        payment_stream.terminate();  // Terminate the stream
-
-        // Simulate the act of terminating the stream again
-        // Again, normally you'd call a function, but here we're simulating directly
-        let result = if payment_stream.is_terminated() {
-            Err(PaymentError::StreamAlreadyTerminated)
-        } else {
-            payment_stream.terminate();
-            Ok(())
-        };
-
-        // Assert
-        assert_eq!(result, Err(PaymentError::StreamAlreadyTerminated));
-    }
-}
\ No newline at end of file
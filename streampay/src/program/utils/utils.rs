@@ -0,0 +1,389 @@
+use solana_program::{
+    account_info::AccountInfo,
+    clock::{Clock, UnixTimestamp},
+    entrypoint::ProgramResult,
+    msg,
+    program::{invoke, invoke_signed},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_instruction,
+    sysvar::{rent::Rent, Sysvar},
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{instruction::harvest_withheld_tokens_to_mint, TransferFeeAmount, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use crate::constants::{BPS_DENOMINATOR, CONFIG_SEED, TREASURY_SEED, VAULT_SEED};
+use crate::error::PaymentError;
+
+/// Returns the cluster's current unix timestamp from the Clock sysvar. Every
+/// time-dependent calculation reads "now" through this function.
+pub fn current_timestamp() -> Result<UnixTimestamp, ProgramError> {
+    Ok(Clock::get()?.unix_timestamp)
+}
+
+/// Returns `fee_bps` basis points of `amount`. Fractions of a unit round up, so
+/// the treasury never collects less than the configured rate.
+pub fn calculate_fee(amount: u64, fee_bps: u16) -> Result<u64, ProgramError> {
+    let denominator = BPS_DENOMINATOR as u128;
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .and_then(|numerator| numerator.checked_add(denominator - 1))
+        .and_then(|numerator| numerator.checked_div(denominator))
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    u64::try_from(fee).map_err(|_| PaymentError::ArithmeticOverflow.into())
+}
+
+/// Returns `bps` basis points of `amount`. Fractions of a unit round down, in
+/// favor of whoever the rest of `amount` goes to.
+pub fn calculate_share(amount: u64, bps: u16) -> u64 {
+    (amount as u128 * bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+/// Derives the vault PDA that escrows the deposit of a payment stream.
+pub fn find_vault_address(payment_stream: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[VAULT_SEED, payment_stream.as_ref()], program_id)
+}
+
+/// Checks that `vault` is the vault PDA of `payment_stream` for the stored bump.
+pub fn check_vault_address(
+    vault: &Pubkey,
+    payment_stream: &Pubkey,
+    vault_bump: u8,
+    program_id: &Pubkey,
+) -> ProgramResult {
+    let expected = Pubkey::create_program_address(
+        &[VAULT_SEED, payment_stream.as_ref(), &[vault_bump]],
+        program_id,
+    )
+    .map_err(|_| PaymentError::InvalidVaultAccount)?;
+
+    if *vault != expected {
+        return Err(PaymentError::InvalidVaultAccount.into());
+    }
+
+    Ok(())
+}
+
+/// Derives the program config PDA.
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+/// Derives the treasury PDA that collects operational fees.
+pub fn find_treasury_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[TREASURY_SEED], program_id)
+}
+
+/// Creates a program-owned account at a PDA, signing for it with `signer_seeds`.
+pub fn create_pda_account<'a>(
+    payer_account: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    space: usize,
+    owner: &Pubkey,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            new_account.key,
+            Rent::get()?.minimum_balance(space),
+            space as u64,
+            owner,
+        ),
+        &[
+            payer_account.clone(),
+            new_account.clone(),
+            system_program_account.clone(),
+        ],
+        &[signer_seeds],
+    )
+}
+
+/// Transfers lamports out of a stream vault, signing for the vault with its seeds.
+pub fn transfer_from_vault<'a>(
+    vault_account: &AccountInfo<'a>,
+    to_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    payment_stream: &Pubkey,
+    vault_bump: u8,
+    amount: u64,
+) -> ProgramResult {
+    transfer_lamports_signed(
+        vault_account,
+        to_account,
+        system_program_account,
+        amount,
+        &[VAULT_SEED, payment_stream.as_ref(), &[vault_bump]],
+    )
+}
+
+/// Transfers lamports out of the treasury, signing for it with its seeds.
+pub fn transfer_from_treasury<'a>(
+    treasury_account: &AccountInfo<'a>,
+    to_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    treasury_bump: u8,
+    amount: u64,
+) -> ProgramResult {
+    transfer_lamports_signed(
+        treasury_account,
+        to_account,
+        system_program_account,
+        amount,
+        &[TREASURY_SEED, &[treasury_bump]],
+    )
+}
+
+fn transfer_lamports_signed<'a>(
+    from_account: &AccountInfo<'a>,
+    to_account: &AccountInfo<'a>,
+    system_program_account: &AccountInfo<'a>,
+    amount: u64,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(
+        &system_instruction::transfer(from_account.key, to_account.key, amount),
+        &[
+            from_account.clone(),
+            to_account.clone(),
+            system_program_account.clone(),
+        ],
+        &[signer_seeds],
+    )
+}
+
+/// Mint extensions streams can hold. Transfer fees are reconciled on every transfer
+/// and interest only changes the UI amount, so raw token amounts stay exact.
+const SUPPORTED_MINT_EXTENSIONS: &[ExtensionType] = &[
+    ExtensionType::TransferFeeConfig,
+    ExtensionType::InterestBearingConfig,
+    ExtensionType::MintCloseAuthority,
+    ExtensionType::MetadataPointer,
+    ExtensionType::TokenMetadata,
+    ExtensionType::GroupPointer,
+    ExtensionType::TokenGroup,
+    ExtensionType::GroupMemberPointer,
+    ExtensionType::TokenGroupMember,
+];
+
+/// Checks that `program_id` is SPL Token or Token-2022.
+pub fn check_token_program(program_id: &Pubkey) -> ProgramResult {
+    if *program_id != spl_token::id() && *program_id != spl_token_2022::id() {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+/// Unpacks an SPL Token or Token-2022 mint, ignoring its extensions.
+pub fn unpack_mint(mint_account: &AccountInfo) -> Result<Mint, ProgramError> {
+    check_token_program(mint_account.owner).map_err(|_| PaymentError::InvalidMint)?;
+
+    let mint_data = mint_account.try_borrow_data()?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| PaymentError::InvalidMint)?;
+    Ok(mint.base)
+}
+
+/// Rejects mints with extensions the program cannot escrow safely, such as
+/// non-transferable, permanent-delegate or transfer-hook mints.
+pub fn check_mint_extensions(mint_account: &AccountInfo) -> ProgramResult {
+    let mint_data = mint_account.try_borrow_data()?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| PaymentError::InvalidMint)?;
+
+    for extension in mint.get_extension_types()? {
+        if !SUPPORTED_MINT_EXTENSIONS.contains(&extension) {
+            msg!("Unsupported mint extension: {:?}", extension);
+            return Err(PaymentError::UnsupportedMintExtension.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the fee Token-2022 withholds when `amount` of the mint is transferred
+/// in the current epoch, or 0 for mints without the transfer-fee extension.
+pub fn calculate_transfer_fee(mint_account: &AccountInfo, amount: u64) -> Result<u64, ProgramError> {
+    let mint_data = mint_account.try_borrow_data()?;
+    let mint = StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| PaymentError::InvalidMint)?;
+
+    match mint.get_extension::<TransferFeeConfig>() {
+        Ok(transfer_fee_config) => transfer_fee_config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or_else(|| PaymentError::InvalidAmount.into()),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Checks that `token_account` is a token account for `mint` owned by `owner`.
+pub fn check_token_account(
+    token_account: &AccountInfo,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> ProgramResult {
+    if token_account_owner(token_account, mint)? != *owner {
+        return Err(PaymentError::InvalidTokenAccount.into());
+    }
+
+    Ok(())
+}
+
+/// Returns the owner of `token_account` after checking that it is a token
+/// account for `mint`.
+pub fn token_account_owner(token_account: &AccountInfo, mint: &Pubkey) -> Result<Pubkey, ProgramError> {
+    check_token_program(token_account.owner).map_err(|_| PaymentError::InvalidTokenAccount)?;
+
+    let account_data = token_account.try_borrow_data()?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data)
+        .map_err(|_| PaymentError::InvalidTokenAccount)?;
+    if account.base.mint != *mint {
+        return Err(PaymentError::InvalidTokenAccount.into());
+    }
+
+    Ok(account.base.owner)
+}
+
+/// Returns the token balance held by `token_account`.
+pub fn token_balance(token_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let account_data = token_account.try_borrow_data()?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data)
+        .map_err(|_| PaymentError::InvalidTokenAccount)?;
+    Ok(account.base.amount)
+}
+
+/// Transfers tokens out of a stream's vault token account, signing for the vault with its seeds.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_tokens_from_vault<'a>(
+    token_program_account: &AccountInfo<'a>,
+    vault_token_account: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    to_token_account: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    payment_stream: &Pubkey,
+    vault_bump: u8,
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    transfer_tokens_signed(
+        token_program_account,
+        vault_token_account,
+        mint_account,
+        to_token_account,
+        vault_account,
+        amount,
+        decimals,
+        &[VAULT_SEED, payment_stream.as_ref(), &[vault_bump]],
+    )
+}
+
+/// Transfers tokens out of a treasury token account, signing for the treasury with its seeds.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_tokens_from_treasury<'a>(
+    token_program_account: &AccountInfo<'a>,
+    treasury_token_account: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    to_token_account: &AccountInfo<'a>,
+    treasury_account: &AccountInfo<'a>,
+    treasury_bump: u8,
+    amount: u64,
+    decimals: u8,
+) -> ProgramResult {
+    transfer_tokens_signed(
+        token_program_account,
+        treasury_token_account,
+        mint_account,
+        to_token_account,
+        treasury_account,
+        amount,
+        decimals,
+        &[TREASURY_SEED, &[treasury_bump]],
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn transfer_tokens_signed<'a>(
+    token_program_account: &AccountInfo<'a>,
+    from_token_account: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    to_token_account: &AccountInfo<'a>,
+    authority_account: &AccountInfo<'a>,
+    amount: u64,
+    decimals: u8,
+    signer_seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(
+        &spl_token_2022::instruction::transfer_checked(
+            token_program_account.key,
+            from_token_account.key,
+            mint_account.key,
+            to_token_account.key,
+            authority_account.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+        &[
+            from_token_account.clone(),
+            mint_account.clone(),
+            to_token_account.clone(),
+            authority_account.clone(),
+            token_program_account.clone(),
+        ],
+        &[signer_seeds],
+    )
+}
+
+/// Returns the Token-2022 transfer fees withheld in `token_account`, or 0 for
+/// accounts without the transfer-fee extension.
+pub fn withheld_transfer_fees(token_account: &AccountInfo) -> Result<u64, ProgramError> {
+    let account_data = token_account.try_borrow_data()?;
+    let account = StateWithExtensions::<TokenAccount>::unpack(&account_data)
+        .map_err(|_| PaymentError::InvalidTokenAccount)?;
+
+    match account.get_extension::<TransferFeeAmount>() {
+        Ok(transfer_fee_amount) => Ok(u64::from(transfer_fee_amount.withheld_amount)),
+        Err(_) => Ok(0),
+    }
+}
+
+/// Closes an emptied vault token account, returning its rent to `destination_account`.
+/// Token-2022 transfer fees withheld in the vault would block the close, so they
+/// are first harvested to the mint, where the mint's withdraw authority collects them.
+pub fn close_vault_token_account<'a>(
+    token_program_account: &AccountInfo<'a>,
+    vault_token_account: &AccountInfo<'a>,
+    mint_account: &AccountInfo<'a>,
+    destination_account: &AccountInfo<'a>,
+    vault_account: &AccountInfo<'a>,
+    payment_stream: &Pubkey,
+    vault_bump: u8,
+) -> ProgramResult {
+    if withheld_transfer_fees(vault_token_account)? > 0 {
+        invoke(
+            &harvest_withheld_tokens_to_mint(token_program_account.key, mint_account.key, &[vault_token_account.key])?,
+            &[mint_account.clone(), vault_token_account.clone(), token_program_account.clone()],
+        )?;
+    }
+
+    invoke_signed(
+        &spl_token_2022::instruction::close_account(
+            token_program_account.key,
+            vault_token_account.key,
+            destination_account.key,
+            vault_account.key,
+            &[],
+        )?,
+        &[
+            vault_token_account.clone(),
+            destination_account.clone(),
+            vault_account.clone(),
+            token_program_account.clone(),
+        ],
+        &[&[VAULT_SEED, payment_stream.as_ref(), &[vault_bump]]],
+    )
+}
@@ -0,0 +1,69 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use solana_program::pubkey::Pubkey;
+use crate::events::{StreamEvent, EVENT_DISCRIMINATOR, EVENT_VERSION};
+use crate::error::EventError;
+
+fn withdrawn_event() -> StreamEvent {
+    StreamEvent::Withdrawn {
+        payment_stream: Pubkey::new_unique(),
+        recipient: Pubkey::new_unique(),
+        destination: Pubkey::new_unique(),
+        amount: 1_000,
+        fee: 10,
+        keeper: Some(Pubkey::new_unique()),
+        tip: 5,
+    }
+}
+
+/// Formats event data the way the runtime logs `sol_log_data`.
+fn program_data_log(data: &[u8]) -> String {
+    format!("Program data: {}", STANDARD.encode(data))
+}
+
+#[test]
+fn test_event_encoding_roundtrip() {
+    let event = withdrawn_event();
+    let data = event.encode();
+
+    assert_eq!(data[..EVENT_DISCRIMINATOR.len()], EVENT_DISCRIMINATOR);
+    assert_eq!(data[EVENT_DISCRIMINATOR.len()], EVENT_VERSION);
+    assert_eq!(StreamEvent::decode(&data), Ok(event));
+}
+
+#[test]
+fn test_event_decoding_rejects_other_versions() {
+    let mut data = withdrawn_event().encode();
+    data[EVENT_DISCRIMINATOR.len()] = EVENT_VERSION + 1;
+
+    assert_eq!(
+        StreamEvent::decode(&data),
+        Err(EventError::UnsupportedVersion(EVENT_VERSION + 1))
+    );
+}
+
+#[test]
+fn test_event_decoding_rejects_foreign_and_truncated_data() {
+    assert_eq!(StreamEvent::decode(b"otherprg\x01"), Err(EventError::NotAStreamEvent));
+    assert_eq!(StreamEvent::decode(&EVENT_DISCRIMINATOR), Err(EventError::InvalidData));
+
+    let data = withdrawn_event().encode();
+    assert_eq!(StreamEvent::decode(&data[..data.len() - 1]), Err(EventError::InvalidData));
+}
+
+#[test]
+fn test_decode_logs_skips_unrelated_lines() {
+    let paused = StreamEvent::Paused {
+        payment_stream: Pubkey::new_unique(),
+        paused_at: 1_700_000_000,
+    };
+    let withdrawn = withdrawn_event();
+    let logs = vec![
+        "Program log: Withdraw instruction received".to_string(),
+        program_data_log(&paused.encode()),
+        program_data_log(b"data logged by another program"),
+        "Program consumed 5000 of 200000 compute units".to_string(),
+        program_data_log(&withdrawn.encode()),
+    ];
+
+    assert_eq!(StreamEvent::decode_logs(&logs), Ok(vec![paused, withdrawn]));
+}
@@ -1,15 +1,46 @@
+use borsh::BorshDeserialize;
 use solana_program_test::*;
 use solana_sdk::{
+    account::AccountSharedData,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::{Clock, UnixTimestamp},
+    instruction::{AccountMeta, Instruction},
+    message::Message,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
     signature::{Keypair, Signer},
-    transaction::Transaction,
+    system_instruction, system_program,
+    transaction::{Transaction, TransactionError},
 };
-use crate::instruction::StreamPayInstruction;
+use spl_associated_token_account::{
+    get_associated_token_address_with_program_id, instruction::create_associated_token_account,
+};
+use spl_token_2022::{
+    extension::{
+        transfer_fee::{instruction::initialize_transfer_fee_config, TransferFeeConfig},
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::{Account as TokenAccount, Mint},
+};
+use crate::instruction::{batch_initialize_streams, StreamEntry, StreamPayInstruction};
 use crate::error::PaymentError;
+use crate::events::StreamEvent;
+use crate::payment_stream::{
+    BlackoutWindow, Breakpoint, CancellationPolicy, PaymentStream, ReleaseCurve, SplitRecipient, StreamSnapshot,
+    StreamStatus,
+};
+use crate::processor::Processor;
+use crate::constants::{MAX_BATCH_STREAMS, MAX_TRANSACTION_SIZE, MINIMUM_AMOUNT_LAMPORTS, MIN_CRANK_INTERVAL};
+use crate::state::{ConfigParams, FeeChargePoint, ProgramConfig};
+use crate::utils::{find_config_address, find_treasury_address, find_vault_address};
 
 // Constants for testing
 const TEST_START_TIME: u64 = 100;
 const TEST_INTERVAL: u64 = 10;
 const TEST_AMOUNT_PER_INTERVAL: u64 = 5;
+const TEST_MINIMUM_DEPOSIT: u64 = 100;
+const TEST_MAX_WITHDRAWAL_AMOUNT: u64 = 5000;
 
 // Utility Functions
 // -----------------
@@ -109,3 +140,2944 @@ async fn test_invalid_withdrawal_amount() {
 }
 
 // Add more test cases as needed...
+
+// Clock Helpers
+// -------------
+
+fn program_test(program_id: Pubkey) -> ProgramTest {
+    ProgramTest::new("streampay", program_id, processor!(Processor::process))
+}
+
+/// Moves the cluster clock to `unix_timestamp` so vesting can be checked at exact boundaries.
+async fn warp_to_timestamp(context: &mut ProgramTestContext, unix_timestamp: UnixTimestamp) {
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = unix_timestamp;
+    context.set_sysvar(&clock);
+}
+
+async fn current_unix_timestamp(context: &mut ProgramTestContext) -> UnixTimestamp {
+    context.banks_client.get_sysvar::<Clock>().await.unwrap().unix_timestamp
+}
+
+/// Creates a funded recipient wallet so small payouts keep it rent-exempt.
+async fn create_recipient(context: &mut ProgramTestContext) -> Keypair {
+    let recipient = Keypair::new();
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::transfer(&context.payer.pubkey(), &recipient.pubkey(), 1_000_000_000)],
+        Some(&context.payer.pubkey()),
+        &[&context.payer],
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(transaction).await.unwrap();
+    recipient
+}
+
+/// Config parameters with the default limits, making the context payer the fee authority.
+fn config_params(context: &ProgramTestContext, fee_bps: u16, fee_charge_point: FeeChargePoint) -> ConfigParams {
+    ConfigParams {
+        minimum_deposit: MINIMUM_AMOUNT_LAMPORTS,
+        max_withdrawal_amount: TEST_MAX_WITHDRAWAL_AMOUNT,
+        fee_bps,
+        fee_charge_point,
+        fee_authority: context.payer.pubkey(),
+        crank_tip_bps: 0,
+    }
+}
+
+/// Records `upgrade_authority` in the program's ProgramData account, as deploying
+/// with the upgradeable loader would.
+fn set_upgrade_authority(context: &mut ProgramTestContext, program_id: &Pubkey, upgrade_authority: &Pubkey) {
+    let (program_data, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    let data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: Some(*upgrade_authority),
+    })
+    .unwrap();
+    let mut account = AccountSharedData::new(1_000_000_000, data.len(), &bpf_loader_upgradeable::id());
+    account.set_data_from_slice(&data);
+    context.set_account(&program_data, &account);
+}
+
+/// Builds an `InitializeConfig` signed by `admin`.
+fn initialize_config_instruction(program_id: &Pubkey, admin: &Pubkey, params: ConfigParams) -> Instruction {
+    let (program_data, _) = Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::InitializeConfig { params }.pack(),
+        vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(find_config_address(program_id).0, false),
+            AccountMeta::new(find_treasury_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(program_data, false),
+        ],
+    )
+}
+
+/// Creates the program config, making the context payer the upgrade authority,
+/// the admin and the fee authority.
+async fn initialize_config(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    fee_bps: u16,
+    fee_charge_point: FeeChargePoint,
+) {
+    let admin = context.payer.pubkey();
+    set_upgrade_authority(context, program_id, &admin);
+    let params = config_params(context, fee_bps, fee_charge_point);
+    let instruction = initialize_config_instruction(program_id, &admin, params);
+    process_instruction(context, instruction, &[]).await.unwrap();
+}
+
+/// Replaces the config parameters, signed by `admin`.
+async fn update_config(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    admin: &Keypair,
+    params: ConfigParams,
+) -> Result<(), BanksClientError> {
+    let data = StreamPayInstruction::UpdateConfig { params }.pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[Instruction::new_with_bytes(
+            *program_id,
+            &data,
+            vec![
+                AccountMeta::new_readonly(admin.pubkey(), true),
+                AccountMeta::new(find_config_address(program_id).0, false),
+            ],
+        )],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, admin],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Hands the admin role from `admin` to `new_admin`.
+async fn transfer_admin(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    admin: &Keypair,
+    new_admin: &Keypair,
+) -> Result<(), BanksClientError> {
+    let data = StreamPayInstruction::TransferAdmin.pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[Instruction::new_with_bytes(
+            *program_id,
+            &data,
+            vec![
+                AccountMeta::new_readonly(admin.pubkey(), true),
+                AccountMeta::new(find_config_address(program_id).0, false),
+                AccountMeta::new_readonly(new_admin.pubkey(), true),
+            ],
+        )],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, admin, new_admin],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Sets the global pause flag, signed by `admin`.
+async fn set_program_paused(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    admin: &Keypair,
+    is_paused: bool,
+    allow_vested_withdrawals: bool,
+) -> Result<(), BanksClientError> {
+    let data = StreamPayInstruction::SetProgramPaused {
+        is_paused,
+        allow_vested_withdrawals,
+    }
+    .pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[Instruction::new_with_bytes(
+            *program_id,
+            &data,
+            vec![
+                AccountMeta::new_readonly(admin.pubkey(), true),
+                AccountMeta::new(find_config_address(program_id).0, false),
+            ],
+        )],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, admin],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Sweeps the lamport fees held by the treasury to `destination`, signed by `fee_authority`.
+async fn collect_lamport_fees(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    fee_authority: &Keypair,
+    destination: &Pubkey,
+) -> Result<(), BanksClientError> {
+    let data = StreamPayInstruction::CollectFees.pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[Instruction::new_with_bytes(
+            *program_id,
+            &data,
+            vec![
+                AccountMeta::new_readonly(fee_authority.pubkey(), true),
+                AccountMeta::new_readonly(find_config_address(program_id).0, false),
+                AccountMeta::new(find_treasury_address(program_id).0, false),
+                AccountMeta::new(*destination, false),
+                AccountMeta::new_readonly(system_program::id(), false),
+            ],
+        )],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, fee_authority],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Creates the stream account and initializes a lamport stream starting at `start_time`.
+async fn initialize_lamport_stream(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    start_time: UnixTimestamp,
+) -> Result<Keypair, BanksClientError> {
+    let payer = context.payer.insecure_clone();
+    initialize_lamport_stream_with(context, program_id, &payer, recipient, start_time, StreamOptions::default()).await
+}
+
+/// Stream settings chosen at creation. `deposit` overrides the amount released
+/// in the stream's single interval, `TEST_AMOUNT_PER_INTERVAL` by default.
+#[derive(Default)]
+struct StreamOptions {
+    deposit: Option<u64>,
+    delegate: Option<Pubkey>,
+    cancellation_policy: CancellationPolicy,
+    cancellation_penalty_bps: u16,
+    transferable_by_recipient: bool,
+    transferable_by_sender: bool,
+    update_requires_recipient_consent: bool,
+    allow_crank: bool,
+    blackout_windows: Vec<BlackoutWindow>,
+    splits: Vec<SplitRecipient>,
+}
+
+/// Like `initialize_lamport_stream`, funded by `payer` and with the given options.
+async fn initialize_lamport_stream_with(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    payer: &Keypair,
+    recipient: &Pubkey,
+    start_time: UnixTimestamp,
+    options: StreamOptions,
+) -> Result<Keypair, BanksClientError> {
+    let payment_stream = Keypair::new();
+    let space = PaymentStream::LEN;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let (vault, _) = find_vault_address(&payment_stream.pubkey(), program_id);
+    let deposit = options.deposit.unwrap_or(TEST_AMOUNT_PER_INTERVAL);
+
+    let data = StreamPayInstruction::InitializeStream {
+        start_time: start_time as u64,
+        interval: TEST_INTERVAL,
+        amount_per_interval: deposit,
+        total_amount: deposit,
+        cliff_time: 0,
+        cliff_amount: 0,
+        end_time: 0,
+        release_curve: ReleaseCurve::Stepped,
+        delegate: options.delegate,
+        cancellation_policy: options.cancellation_policy,
+        cancellation_penalty_bps: options.cancellation_penalty_bps,
+        transferable_by_recipient: options.transferable_by_recipient,
+        transferable_by_sender: options.transferable_by_sender,
+        update_requires_recipient_consent: options.update_requires_recipient_consent,
+        allow_crank: options.allow_crank,
+        blackout_windows: options.blackout_windows,
+        splits: options.splits,
+        mint: None,
+    }
+    .pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &payment_stream.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            Instruction::new_with_bytes(
+                *program_id,
+                &data,
+                vec![
+                    AccountMeta::new(payer.pubkey(), true),
+                    AccountMeta::new(payment_stream.pubkey(), false),
+                    AccountMeta::new_readonly(*recipient, false),
+                    AccountMeta::new(vault, false),
+                    AccountMeta::new_readonly(find_config_address(program_id).0, false),
+                    AccountMeta::new(find_treasury_address(program_id).0, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, payer, &payment_stream],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await?;
+
+    Ok(payment_stream)
+}
+
+/// Withdraws lamports from `payment_stream` to `recipient`.
+async fn withdraw_lamports(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    recipient: &Keypair,
+    payment_stream: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let instruction = withdraw_instruction(program_id, &recipient.pubkey(), true, payment_stream, amount);
+    process_instruction(context, instruction, &[recipient]).await
+}
+
+/// Builds a lamport `Withdraw` naming `recipient`, signed by it when `is_signer`.
+fn withdraw_instruction(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    is_signer: bool,
+    payment_stream: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::Withdraw { amount }.pack(),
+        vec![
+            AccountMeta::new(*recipient, is_signer),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_treasury_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a lamport `TopUp` of `amount`, funded and signed by `funder`.
+fn top_up_instruction(program_id: &Pubkey, funder: &Pubkey, payment_stream: &Pubkey, amount: u64) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::TopUp { amount }.pack(),
+        vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_treasury_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds a `TransferRecipient` signed by `authority`, handing the stream to `new_recipient`.
+fn transfer_recipient_instruction(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    payment_stream: &Pubkey,
+    new_recipient: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::TransferRecipient.pack(),
+        vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new_readonly(*new_recipient, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Builds a lamport `TerminateStream` naming `payer` and `recipient`, each signed
+/// when its flag is set.
+fn terminate_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payer_is_signer: bool,
+    recipient: &Pubkey,
+    recipient_is_signer: bool,
+    payment_stream: &Pubkey,
+) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::TerminateStream.pack(),
+        vec![
+            AccountMeta::new(*payer, payer_is_signer),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(*recipient, recipient_is_signer),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(find_treasury_address(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    )
+}
+
+/// Builds an instruction that only takes the stream authority, the stream and the
+/// config, such as `PauseStream`, signed by `authority` when `is_signer`.
+fn stream_authority_instruction(
+    program_id: &Pubkey,
+    instruction: StreamPayInstruction,
+    authority: &Pubkey,
+    is_signer: bool,
+    payment_stream: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &instruction.pack(),
+        vec![
+            AccountMeta::new_readonly(*authority, is_signer),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+/// Sends `instruction` with the context payer paying fees, plus any extra `signers`.
+async fn process_instruction(
+    context: &mut ProgramTestContext,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let payer = context.payer.insecure_clone();
+    let mut all_signers = vec![&payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &all_signers,
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Asserts that a transaction failed with `expected` from the program.
+/// Processes `instruction` and decodes the stream events it logged.
+async fn process_instruction_events(
+    context: &mut ProgramTestContext,
+    instruction: Instruction,
+    signers: &[&Keypair],
+) -> Vec<StreamEvent> {
+    let payer = context.payer.insecure_clone();
+    let mut all_signers = vec![&payer];
+    all_signers.extend_from_slice(signers);
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &all_signers,
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    let result = context.banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    assert!(result.result.is_ok());
+    StreamEvent::decode_logs(&result.metadata.unwrap().log_messages).unwrap()
+}
+
+fn assert_program_error(result: Result<(), BanksClientError>, expected: ProgramError) {
+    match result.unwrap_err().unwrap() {
+        TransactionError::InstructionError(_, error) => {
+            assert_eq!(ProgramError::try_from(error), Ok(expected));
+        }
+        error => panic!("unexpected transaction error: {:?}", error),
+    }
+}
+
+// Clock Test Cases
+// ----------------
+
+#[tokio::test]
+async fn test_initialize_rejects_start_time_in_the_past() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+
+    let now = current_unix_timestamp(&mut context).await;
+    let result = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), now - 1).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_withdraw_at_interval_boundary() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+
+    // One second before the first interval completes nothing has vested
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp - 1).await;
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert!(result.is_err());
+
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert!(result.is_ok());
+}
+
+// Treasury Test Cases
+// -------------------
+
+#[tokio::test]
+async fn test_fees_route_to_treasury_and_collect() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 10_000, FeeChargePoint::Both).await;
+    let recipient = create_recipient(&mut context).await;
+    let (treasury, _) = find_treasury_address(&program_id);
+    let treasury_reserve = context.banks_client.get_balance(treasury).await.unwrap();
+
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+
+    // A 100% deposit fee doubles what the payer sends, and the extra goes to the treasury
+    let treasury_balance = context.banks_client.get_balance(treasury).await.unwrap();
+    assert_eq!(treasury_balance - treasury_reserve, TEST_AMOUNT_PER_INTERVAL);
+
+    // With a 100% withdrawal fee the whole payout goes to the treasury as well
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await
+    .unwrap();
+    assert_eq!(context.banks_client.get_balance(recipient.pubkey()).await.unwrap(), recipient_balance);
+    let treasury_balance = context.banks_client.get_balance(treasury).await.unwrap();
+    assert_eq!(treasury_balance - treasury_reserve, 2 * TEST_AMOUNT_PER_INTERVAL);
+
+    // Collecting leaves only the treasury's rent-exempt reserve behind
+    let fee_authority = context.payer.insecure_clone();
+    collect_lamport_fees(&mut context, &program_id, &fee_authority, &recipient.pubkey())
+        .await
+        .unwrap();
+    assert_eq!(context.banks_client.get_balance(treasury).await.unwrap(), treasury_reserve);
+}
+
+#[tokio::test]
+async fn test_collect_fees_rejects_other_signers() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 150, FeeChargePoint::Deposit).await;
+    let impostor = create_recipient(&mut context).await;
+
+    let result = collect_lamport_fees(&mut context, &program_id, &impostor, &impostor.pubkey()).await;
+    assert!(result.is_err());
+}
+
+// Program Config Test Cases
+// -------------------------
+
+#[tokio::test]
+async fn test_initialize_config_requires_upgrade_authority() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let upgrade_authority = context.payer.pubkey();
+    set_upgrade_authority(&mut context, &program_id, &upgrade_authority);
+    let params = config_params(&context, 0, FeeChargePoint::Deposit);
+
+    // Anyone else racing the deployer to the admin role is turned away
+    let stranger = create_recipient(&mut context).await;
+    let instruction = initialize_config_instruction(&program_id, &stranger.pubkey(), params);
+    let result = process_instruction(&mut context, instruction, &[&stranger]).await;
+    assert_program_error(result, PaymentError::InvalidUpgradeAuthority.into());
+
+    let instruction = initialize_config_instruction(&program_id, &upgrade_authority, params);
+    process_instruction(&mut context, instruction, &[]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_config_limits_apply_without_redeploying() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let admin = context.payer.insecure_clone();
+
+    // Raising the minimum deposit above the stream's total rejects new streams
+    let mut params = config_params(&context, 0, FeeChargePoint::Deposit);
+    params.minimum_deposit = TEST_MINIMUM_DEPOSIT;
+    update_config(&mut context, &program_id, &admin, params).await.unwrap();
+
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let result = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time).await;
+    assert!(result.is_err());
+
+    // Lowering it again lets the stream through, and the withdrawal limit caps payouts
+    params.minimum_deposit = MINIMUM_AMOUNT_LAMPORTS;
+    params.max_withdrawal_amount = TEST_AMOUNT_PER_INTERVAL - 1;
+    update_config(&mut context, &program_id, &admin, params).await.unwrap();
+
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_transfer_admin_hands_over_config_updates() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let admin = context.payer.insecure_clone();
+    let new_admin = create_recipient(&mut context).await;
+    let params = config_params(&context, 150, FeeChargePoint::Both);
+
+    let result = update_config(&mut context, &program_id, &new_admin, params).await;
+    assert!(result.is_err());
+
+    transfer_admin(&mut context, &program_id, &admin, &new_admin).await.unwrap();
+
+    let result = update_config(&mut context, &program_id, &admin, params).await;
+    assert!(result.is_err());
+    update_config(&mut context, &program_id, &new_admin, params).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_program_pause_freezes_streams() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let admin = context.payer.insecure_clone();
+    let recipient = create_recipient(&mut context).await;
+
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + 2 * TEST_INTERVAL as UnixTimestamp).await;
+
+    // Only the admin can pull the circuit breaker
+    let result = set_program_paused(&mut context, &program_id, &recipient, true, false).await;
+    assert!(result.is_err());
+    set_program_paused(&mut context, &program_id, &admin, true, false).await.unwrap();
+
+    let result = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time + 1_000).await;
+    assert!(result.is_err());
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert!(result.is_err());
+
+    // Vested withdrawals can be let through while everything else stays frozen
+    set_program_paused(&mut context, &program_id, &admin, true, true).await.unwrap();
+    withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await
+    .unwrap();
+
+    set_program_paused(&mut context, &program_id, &admin, false, false).await.unwrap();
+    initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time + 1_000)
+        .await
+        .unwrap();
+}
+
+// Authorization Test Cases
+// ------------------------
+
+/// A delegated stream plus a funded stranger that holds no role on it. None of the
+/// roles is the transaction fee payer, so each can be left unsigned.
+struct StreamRoles {
+    program_id: Pubkey,
+    payer: Keypair,
+    recipient: Keypair,
+    delegate: Keypair,
+    stranger: Keypair,
+    payment_stream: Pubkey,
+}
+
+async fn stream_roles(context: &mut ProgramTestContext, program_id: Pubkey) -> StreamRoles {
+    initialize_config(context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(context).await;
+    let recipient = create_recipient(context).await;
+    let delegate = create_recipient(context).await;
+    let stranger = create_recipient(context).await;
+
+    let start_time = current_unix_timestamp(context).await + 100;
+    let options = StreamOptions {
+        delegate: Some(delegate.pubkey()),
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    warp_to_timestamp(context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    StreamRoles {
+        program_id,
+        payer,
+        recipient,
+        delegate,
+        stranger,
+        payment_stream: payment_stream.pubkey(),
+    }
+}
+
+#[tokio::test]
+async fn test_initialize_requires_payer_signature() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let payment_stream = Keypair::new();
+    let (vault, _) = find_vault_address(&payment_stream.pubkey(), &program_id);
+
+    let data = StreamPayInstruction::InitializeStream {
+        start_time: (current_unix_timestamp(&mut context).await + 100) as u64,
+        interval: TEST_INTERVAL,
+        amount_per_interval: TEST_AMOUNT_PER_INTERVAL,
+        total_amount: TEST_AMOUNT_PER_INTERVAL,
+        cliff_time: 0,
+        cliff_amount: 0,
+        end_time: 0,
+        release_curve: ReleaseCurve::Stepped,
+        delegate: None,
+        cancellation_policy: CancellationPolicy::PayerOnly,
+        cancellation_penalty_bps: 0,
+        transferable_by_recipient: false,
+        transferable_by_sender: false,
+        update_requires_recipient_consent: false,
+        allow_crank: false,
+        blackout_windows: Vec::new(),
+        splits: Vec::new(),
+        mint: None,
+    }
+    .pack();
+    let space = PaymentStream::LEN;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &payment_stream.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                &program_id,
+            ),
+            Instruction::new_with_bytes(
+                program_id,
+                &data,
+                vec![
+                    AccountMeta::new(payer.pubkey(), false),
+                    AccountMeta::new(payment_stream.pubkey(), false),
+                    AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                    AccountMeta::new(vault, false),
+                    AccountMeta::new_readonly(find_config_address(&program_id).0, false),
+                    AccountMeta::new(find_treasury_address(&program_id).0, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                ],
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, &payment_stream],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    let result = context.banks_client.process_transaction(transaction).await;
+    assert_program_error(result, PaymentError::AuthorityNotSigner.into());
+}
+
+#[tokio::test]
+async fn test_withdraw_permission_matrix() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+
+    for signer in [&roles.payer, &roles.delegate, &roles.stranger] {
+        let instruction = withdraw_instruction(
+            &roles.program_id,
+            &signer.pubkey(),
+            true,
+            &roles.payment_stream,
+            TEST_AMOUNT_PER_INTERVAL,
+        );
+        let result = process_instruction(&mut context, instruction, &[signer]).await;
+        assert_program_error(result, PaymentError::InvalidRecipient.into());
+    }
+
+    let instruction = withdraw_instruction(
+        &roles.program_id,
+        &roles.recipient.pubkey(),
+        false,
+        &roles.payment_stream,
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    let result = process_instruction(&mut context, instruction, &[]).await;
+    assert_program_error(result, PaymentError::RecipientNotSigner.into());
+}
+
+#[tokio::test]
+async fn test_terminate_permission_matrix() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+
+    for signer in [&roles.recipient, &roles.delegate, &roles.stranger] {
+        let instruction = terminate_instruction(
+            &roles.program_id,
+            &signer.pubkey(),
+            true,
+            &roles.recipient.pubkey(),
+            false,
+            &roles.payment_stream,
+        );
+        let result = process_instruction(&mut context, instruction, &[signer]).await;
+        assert_program_error(result, PaymentError::InvalidPayer.into());
+    }
+
+    let instruction = terminate_instruction(
+        &roles.program_id,
+        &roles.payer.pubkey(),
+        false,
+        &roles.recipient.pubkey(),
+        false,
+        &roles.payment_stream,
+    );
+    let result = process_instruction(&mut context, instruction, &[]).await;
+    assert_program_error(result, PaymentError::AuthorityNotSigner.into());
+
+    // The default policy only lets the payer terminate
+    let instruction = terminate_instruction(
+        &roles.program_id,
+        &roles.payer.pubkey(),
+        false,
+        &roles.recipient.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    let result = process_instruction(&mut context, instruction, &[&roles.recipient]).await;
+    assert_program_error(result, PaymentError::TerminationNotAllowed.into());
+}
+
+#[tokio::test]
+async fn test_stream_management_permission_matrix() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+
+    let instructions = || {
+        [
+            StreamPayInstruction::UpdateStream {
+                interval: TEST_INTERVAL,
+                amount_per_interval: TEST_AMOUNT_PER_INTERVAL,
+            },
+            StreamPayInstruction::PauseStream,
+            StreamPayInstruction::ResumeStream,
+        ]
+    };
+
+    for instruction in instructions() {
+        for signer in [&roles.recipient, &roles.stranger] {
+            let instruction = stream_authority_instruction(
+                &roles.program_id,
+                instruction.clone(),
+                &signer.pubkey(),
+                true,
+                &roles.payment_stream,
+            );
+            let result = process_instruction(&mut context, instruction, &[signer]).await;
+            assert_program_error(result, PaymentError::InvalidAuthority.into());
+        }
+
+        for authority in [&roles.payer, &roles.delegate] {
+            let instruction = stream_authority_instruction(
+                &roles.program_id,
+                instruction.clone(),
+                &authority.pubkey(),
+                false,
+                &roles.payment_stream,
+            );
+            let result = process_instruction(&mut context, instruction, &[]).await;
+            assert_program_error(result, PaymentError::AuthorityNotSigner.into());
+        }
+    }
+
+    // The delegate can pause on the payer's behalf, and the payer can resume
+    let instruction = stream_authority_instruction(
+        &roles.program_id,
+        StreamPayInstruction::PauseStream,
+        &roles.delegate.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    process_instruction(&mut context, instruction, &[&roles.delegate]).await.unwrap();
+    let instruction = stream_authority_instruction(
+        &roles.program_id,
+        StreamPayInstruction::ResumeStream,
+        &roles.payer.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    process_instruction(&mut context, instruction, &[&roles.payer]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_config_permission_matrix() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+    let (config, _) = find_config_address(&program_id);
+    let params = config_params(&context, 0, FeeChargePoint::Deposit);
+
+    // Move the admin role off the fee payer so it can be left unsigned
+    let fee_payer = context.payer.insecure_clone();
+    let admin = create_recipient(&mut context).await;
+    transfer_admin(&mut context, &program_id, &fee_payer, &admin).await.unwrap();
+
+    let admin_instructions = [
+        StreamPayInstruction::UpdateConfig { params },
+        StreamPayInstruction::SetProgramPaused {
+            is_paused: true,
+            allow_vested_withdrawals: false,
+        },
+    ];
+    for instruction in admin_instructions {
+        for signer in [&roles.payer, &roles.recipient, &roles.delegate, &roles.stranger] {
+            let instruction = Instruction::new_with_bytes(
+                program_id,
+                &instruction.pack(),
+                vec![AccountMeta::new_readonly(signer.pubkey(), true), AccountMeta::new(config, false)],
+            );
+            let result = process_instruction(&mut context, instruction, &[signer]).await;
+            assert_program_error(result, PaymentError::InvalidAdmin.into());
+        }
+
+        let instruction = Instruction::new_with_bytes(
+            program_id,
+            &instruction.pack(),
+            vec![AccountMeta::new_readonly(admin.pubkey(), false), AccountMeta::new(config, false)],
+        );
+        let result = process_instruction(&mut context, instruction, &[]).await;
+        assert_program_error(result, ProgramError::MissingRequiredSignature);
+    }
+
+    // Handing the admin role over needs the new admin's signature too
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &StreamPayInstruction::TransferAdmin.pack(),
+        vec![
+            AccountMeta::new_readonly(admin.pubkey(), true),
+            AccountMeta::new(config, false),
+            AccountMeta::new_readonly(roles.stranger.pubkey(), false),
+        ],
+    );
+    let result = process_instruction(&mut context, instruction, &[&admin]).await;
+    assert_program_error(result, ProgramError::MissingRequiredSignature);
+
+    let result = collect_lamport_fees(&mut context, &program_id, &roles.stranger, &roles.stranger.pubkey()).await;
+    assert_program_error(result, PaymentError::InvalidFeeAuthority.into());
+}
+
+// Cancellation Test Cases
+// -----------------------
+
+#[tokio::test]
+async fn test_cancellation_policy_limits_who_terminates() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+
+    for (cancellation_policy, payer_allowed, recipient_allowed) in [
+        (CancellationPolicy::PayerOnly, true, false),
+        (CancellationPolicy::RecipientOnly, false, true),
+        (CancellationPolicy::Both, true, true),
+        (CancellationPolicy::Neither, false, false),
+    ] {
+        for (terminating_party, allowed) in [(&payer, payer_allowed), (&recipient, recipient_allowed)] {
+            let options = StreamOptions {
+                cancellation_policy,
+                ..StreamOptions::default()
+            };
+            let payment_stream =
+                initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+                    .await
+                    .unwrap();
+
+            let is_payer = terminating_party.pubkey() == payer.pubkey();
+            let instruction = terminate_instruction(
+                &program_id,
+                &payer.pubkey(),
+                is_payer,
+                &recipient.pubkey(),
+                !is_payer,
+                &payment_stream.pubkey(),
+            );
+            let result = process_instruction(&mut context, instruction, &[terminating_party]).await;
+            if allowed {
+                result.unwrap();
+            } else {
+                assert_program_error(result, PaymentError::TerminationNotAllowed.into());
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_termination_penalty_pays_counterparty() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+
+    // Nothing has vested yet, so a payer cancelling with a 100% penalty hands the
+    // whole deposit to the recipient
+    let options = StreamOptions {
+        cancellation_policy: CancellationPolicy::Both,
+        cancellation_penalty_bps: 10_000,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+
+    let instruction = terminate_instruction(
+        &program_id,
+        &payer.pubkey(),
+        true,
+        &recipient.pubkey(),
+        false,
+        &payment_stream.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    let recipient_gain = context.banks_client.get_balance(recipient.pubkey()).await.unwrap() - recipient_balance;
+    assert_eq!(recipient_gain, TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[tokio::test]
+async fn test_recipient_termination_penalty_pays_payer() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        deposit: Some(TEST_MINIMUM_DEPOSIT),
+        cancellation_policy: CancellationPolicy::Both,
+        cancellation_penalty_bps: 1_000,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    let payer_balance = context.banks_client.get_balance(payer.pubkey()).await.unwrap();
+
+    let instruction = terminate_instruction(
+        &program_id,
+        &payer.pubkey(),
+        false,
+        &recipient.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+
+    // The whole deposit vested, and the recipient forfeits 10% of it to the payer,
+    // who also gets back the vault's rent-exempt reserve
+    let vault_reserve = context.banks_client.get_rent().await.unwrap().minimum_balance(0);
+    let recipient_gain = context.banks_client.get_balance(recipient.pubkey()).await.unwrap() - recipient_balance;
+    let payer_gain = context.banks_client.get_balance(payer.pubkey()).await.unwrap() - payer_balance;
+    assert_eq!(recipient_gain, TEST_MINIMUM_DEPOSIT * 9 / 10);
+    assert_eq!(payer_gain, TEST_MINIMUM_DEPOSIT / 10 + vault_reserve);
+}
+
+// Recipient Transfer Test Cases
+// -----------------------------
+
+#[tokio::test]
+async fn test_transfer_recipient_hands_over_vested_funds() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let new_recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        transferable_by_recipient: true,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    let instruction = transfer_recipient_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &new_recipient.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+
+    // The funds that vested before the transfer now belong to the new recipient
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert_program_error(result, PaymentError::InvalidRecipient.into());
+    withdraw_lamports(
+        &mut context,
+        &program_id,
+        &new_recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_recipient_follows_transfer_flags() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+
+    for (transferable_by_recipient, transferable_by_sender) in [(false, false), (true, false), (false, true)] {
+        let options = StreamOptions {
+            transferable_by_recipient,
+            transferable_by_sender,
+            ..StreamOptions::default()
+        };
+        let payment_stream =
+            initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+                .await
+                .unwrap();
+
+        for (authority, allowed) in [(&recipient, transferable_by_recipient), (&payer, transferable_by_sender)] {
+            let instruction = transfer_recipient_instruction(
+                &program_id,
+                &authority.pubkey(),
+                &payment_stream.pubkey(),
+                &Pubkey::new_unique(),
+            );
+            let result = process_instruction(&mut context, instruction, &[authority]).await;
+            if allowed {
+                result.unwrap();
+            } else {
+                assert_program_error(result, PaymentError::TransferNotAllowed.into());
+            }
+        }
+    }
+}
+
+// Top-up Test Cases
+// -----------------
+
+#[tokio::test]
+async fn test_top_up_revives_dry_stream() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let funder = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+
+    // The single funded interval is paid out, then the stream sits dry
+    let dry_time = start_time + 3 * TEST_INTERVAL as UnixTimestamp;
+    warp_to_timestamp(&mut context, dry_time).await;
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+        .await
+        .unwrap();
+
+    // Anyone can fund the next interval, which accrues from the top-up on
+    let instruction = top_up_instruction(
+        &program_id,
+        &funder.pubkey(),
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    process_instruction(&mut context, instruction, &[&funder]).await.unwrap();
+
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    let stream_state = PaymentStream::unpack(&account.data).unwrap();
+    assert_eq!(stream_state.total_amount, 2 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(stream_state.withdrawn_amount, TEST_AMOUNT_PER_INTERVAL);
+
+    let result = withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+
+    let top_up_time = current_unix_timestamp(&mut context).await;
+    warp_to_timestamp(&mut context, top_up_time + TEST_INTERVAL as UnixTimestamp).await;
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_top_up_requires_minimum_deposit() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let admin = context.payer.insecure_clone();
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+
+    let mut params = config_params(&context, 0, FeeChargePoint::Deposit);
+    params.minimum_deposit = 2 * TEST_AMOUNT_PER_INTERVAL;
+    update_config(&mut context, &program_id, &admin, params).await.unwrap();
+
+    // Dust top-ups are held to the same minimum as new streams
+    let instruction = top_up_instruction(
+        &program_id,
+        &admin.pubkey(),
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    let result = process_instruction(&mut context, instruction, &[]).await;
+    assert_program_error(result, PaymentError::InvalidAmount.into());
+
+    let instruction = top_up_instruction(
+        &program_id,
+        &admin.pubkey(),
+        &payment_stream.pubkey(),
+        2 * TEST_AMOUNT_PER_INTERVAL,
+    );
+    process_instruction(&mut context, instruction, &[]).await.unwrap();
+}
+
+// Stream Update Test Cases
+// ------------------------
+
+/// Builds an `UpdateStream` doubling the rate, signed by the payer and, when
+/// given, co-signed by the recipient.
+fn update_stream_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_stream: &Pubkey,
+    recipient: Option<&Pubkey>,
+) -> Instruction {
+    let mut instruction = stream_authority_instruction(
+        program_id,
+        StreamPayInstruction::UpdateStream {
+            interval: TEST_INTERVAL,
+            amount_per_interval: 2 * TEST_AMOUNT_PER_INTERVAL,
+        },
+        payer,
+        true,
+        payment_stream,
+    );
+    if let Some(recipient) = recipient {
+        instruction.accounts.push(AccountMeta::new_readonly(*recipient, true));
+    }
+    instruction
+}
+
+#[tokio::test]
+async fn test_update_stream_settles_accrued_funds_first() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream_with(
+        &mut context,
+        &program_id,
+        &payer,
+        &recipient.pubkey(),
+        start_time,
+        StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+    let instruction = top_up_instruction(
+        &program_id,
+        &payer.pubkey(),
+        &payment_stream.pubkey(),
+        4 * TEST_AMOUNT_PER_INTERVAL,
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    // Two intervals vest under the old rate before it doubles
+    warp_to_timestamp(&mut context, start_time + 2 * TEST_INTERVAL as UnixTimestamp).await;
+    let instruction = update_stream_instruction(&program_id, &payer.pubkey(), &payment_stream.pubkey(), None);
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    let stream_state = PaymentStream::unpack(&account.data).unwrap();
+    assert_eq!(stream_state.accrued_unwithdrawn, 2 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(stream_state.total_amount, 3 * TEST_AMOUNT_PER_INTERVAL);
+
+    // The old rate is not applied again to the time that already passed
+    withdraw_lamports(
+        &mut context,
+        &program_id,
+        &recipient,
+        &payment_stream.pubkey(),
+        2 * TEST_AMOUNT_PER_INTERVAL,
+    )
+    .await
+    .unwrap();
+    let result = withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), 1).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+}
+
+#[tokio::test]
+async fn test_update_stream_requires_recipient_consent() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+
+    // The recipient opts in to approving every change to the terms
+    let instruction = stream_authority_instruction(
+        &roles.program_id,
+        StreamPayInstruction::RequireUpdateConsent,
+        &roles.recipient.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    let events = process_instruction_events(&mut context, instruction, &[&roles.recipient]).await;
+    assert_eq!(
+        events,
+        vec![StreamEvent::UpdateConsentRequired {
+            payment_stream: roles.payment_stream,
+            required_by: roles.recipient.pubkey(),
+        }]
+    );
+
+    let instruction = stream_authority_instruction(
+        &roles.program_id,
+        StreamPayInstruction::RequireUpdateConsent,
+        &roles.stranger.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    let result = process_instruction(&mut context, instruction, &[&roles.stranger]).await;
+    assert_program_error(result, PaymentError::InvalidRecipient.into());
+
+    let instruction = update_stream_instruction(
+        &roles.program_id,
+        &roles.payer.pubkey(),
+        &roles.payment_stream,
+        Some(&roles.stranger.pubkey()),
+    );
+    let result = process_instruction(&mut context, instruction, &[&roles.payer, &roles.stranger]).await;
+    assert_program_error(result, PaymentError::InvalidRecipient.into());
+
+    // The recipient has to follow the config PDA, not stand in for it
+    let mut instruction = update_stream_instruction(
+        &roles.program_id,
+        &roles.payer.pubkey(),
+        &roles.payment_stream,
+        Some(&roles.recipient.pubkey()),
+    );
+    instruction.accounts.swap(2, 3);
+    let result = process_instruction(&mut context, instruction.clone(), &[&roles.payer, &roles.recipient]).await;
+    assert_program_error(result, PaymentError::InvalidConfigAccount.into());
+
+    instruction.accounts.swap(2, 3);
+    process_instruction(&mut context, instruction, &[&roles.payer, &roles.recipient])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_update_split_stream_requires_every_recipients_consent() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let first = create_recipient(&mut context).await;
+    let second = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        update_requires_recipient_consent: true,
+        splits: vec![
+            SplitRecipient::new(first.pubkey(), 6_000),
+            SplitRecipient::new(second.pubkey(), 4_000),
+        ],
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &first.pubkey(), start_time, options)
+            .await
+            .unwrap();
+
+    // One recipient cannot agree to new terms on the other's behalf
+    let mut instruction =
+        update_stream_instruction(&program_id, &payer.pubkey(), &payment_stream.pubkey(), Some(&first.pubkey()));
+    let result = process_instruction(&mut context, instruction.clone(), &[&payer, &first]).await;
+    assert_program_error(result, ProgramError::NotEnoughAccountKeys);
+    instruction.accounts.push(AccountMeta::new_readonly(second.pubkey(), false));
+    let result = process_instruction(&mut context, instruction.clone(), &[&payer, &first]).await;
+    assert_program_error(result, PaymentError::RecipientNotSigner.into());
+
+    instruction.accounts[4].is_signer = true;
+    process_instruction(&mut context, instruction, &[&payer, &first, &second]).await.unwrap();
+}
+
+// Stream Pause Test Cases
+// -----------------------
+
+#[tokio::test]
+async fn test_paused_stream_keeps_vested_funds_withdrawable() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream_with(
+        &mut context,
+        &program_id,
+        &payer,
+        &recipient.pubkey(),
+        start_time,
+        StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+    let instruction = top_up_instruction(
+        &program_id,
+        &payer.pubkey(),
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    // One interval vests, then the stream is paused for a long while
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let instruction = stream_authority_instruction(
+        &program_id,
+        StreamPayInstruction::PauseStream,
+        &payer.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    warp_to_timestamp(&mut context, start_time + 10 * TEST_INTERVAL as UnixTimestamp).await;
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+        .await
+        .unwrap();
+    let result = withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), 1).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+
+    // After the resume the paused time does not count towards the next interval
+    let instruction = stream_authority_instruction(
+        &program_id,
+        StreamPayInstruction::ResumeStream,
+        &payer.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+    let result = withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), 1).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+
+    let resume_time = current_unix_timestamp(&mut context).await;
+    warp_to_timestamp(&mut context, resume_time + TEST_INTERVAL as UnixTimestamp).await;
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+        .await
+        .unwrap();
+}
+
+// Blackout Window Test Cases
+// --------------------------
+
+#[tokio::test]
+async fn test_blackout_window_pauses_vesting_without_transactions() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    // Nothing vests in the first interval, so the only one funded ends a full
+    // interval later than it otherwise would
+    let options = StreamOptions {
+        blackout_windows: vec![BlackoutWindow {
+            start: start_time,
+            end: start_time + interval,
+        }],
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+
+    warp_to_timestamp(&mut context, start_time + interval).await;
+    let result = withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), 1).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+
+    warp_to_timestamp(&mut context, start_time + 2 * interval).await;
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_update_blackout_windows_rejects_past_windows() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+    let now = current_unix_timestamp(&mut context).await;
+
+    for (start, expected) in [(now - 1, Err(PaymentError::InvalidBlackoutWindows)), (now + 1_000, Ok(()))] {
+        let instruction = stream_authority_instruction(
+            &roles.program_id,
+            StreamPayInstruction::UpdateBlackoutWindows {
+                blackout_windows: vec![BlackoutWindow {
+                    start,
+                    end: start + TEST_INTERVAL as UnixTimestamp,
+                }],
+            },
+            &roles.delegate.pubkey(),
+            true,
+            &roles.payment_stream,
+        );
+        let result = process_instruction(&mut context, instruction, &[&roles.delegate]).await;
+        match expected {
+            Ok(()) => result.unwrap(),
+            Err(error) => assert_program_error(result, error.into()),
+        }
+    }
+}
+
+// Split Stream Test Cases
+// -----------------------
+
+#[tokio::test]
+async fn test_split_stream_pays_each_recipient_its_share() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let first = create_recipient(&mut context).await;
+    let second = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        splits: vec![
+            SplitRecipient::new(first.pubkey(), 6_000),
+            SplitRecipient::new(second.pubkey(), 4_000),
+        ],
+        cancellation_policy: CancellationPolicy::Both,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &first.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    // The first recipient can only take its own 60%
+    let result = withdraw_lamports(&mut context, &program_id, &first, &payment_stream.pubkey(), 4).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+    withdraw_lamports(&mut context, &program_id, &first, &payment_stream.pubkey(), 3).await.unwrap();
+
+    // Neither recipient may end the other's share of the stream
+    let mut instruction = terminate_instruction(
+        &program_id,
+        &payer.pubkey(),
+        false,
+        &first.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+    );
+    instruction.accounts.push(AccountMeta::new(first.pubkey(), false));
+    instruction.accounts.push(AccountMeta::new(second.pubkey(), false));
+    let result = process_instruction(&mut context, instruction, &[&first]).await;
+    assert_program_error(result, PaymentError::TerminationNotAllowed.into());
+
+    // Terminating pays the second recipient the share it never withdrew
+    let second_balance = context.banks_client.get_balance(second.pubkey()).await.unwrap();
+    let mut instruction = terminate_instruction(
+        &program_id,
+        &payer.pubkey(),
+        true,
+        &first.pubkey(),
+        false,
+        &payment_stream.pubkey(),
+    );
+    instruction.accounts.push(AccountMeta::new(first.pubkey(), false));
+    instruction.accounts.push(AccountMeta::new(second.pubkey(), false));
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    let second_gain = context.banks_client.get_balance(second.pubkey()).await.unwrap() - second_balance;
+    assert_eq!(second_gain, 2);
+}
+
+// Batch Creation Test Cases
+// -------------------------
+
+/// A one-interval stepped stream to `recipient`, as created by `initialize_lamport_stream`.
+fn stream_entry(recipient: &Pubkey, start_time: UnixTimestamp) -> StreamEntry {
+    StreamEntry {
+        recipient: *recipient,
+        start_time: start_time as u64,
+        interval: TEST_INTERVAL,
+        amount_per_interval: TEST_AMOUNT_PER_INTERVAL,
+        total_amount: TEST_AMOUNT_PER_INTERVAL,
+        cliff_time: 0,
+        cliff_amount: 0,
+        end_time: 0,
+        release_curve: ReleaseCurve::Stepped,
+    }
+}
+
+#[tokio::test]
+async fn test_batch_initialize_streams_creates_every_stream() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let mut recipients = Vec::new();
+    for _ in 0..3 {
+        recipients.push(create_recipient(&mut context).await);
+    }
+    let stream_accounts: Vec<Keypair> = recipients.iter().map(|_| Keypair::new()).collect();
+    let streams: Vec<(Pubkey, StreamEntry)> = stream_accounts
+        .iter()
+        .zip(&recipients)
+        .map(|(payment_stream, recipient)| (payment_stream.pubkey(), stream_entry(&recipient.pubkey(), start_time)))
+        .collect();
+
+    let instructions = batch_initialize_streams(&program_id, &payer.pubkey(), &streams, None);
+    assert_eq!(instructions.len(), 1);
+    let mut signers = vec![&payer];
+    signers.extend(stream_accounts.iter());
+    process_instruction(&mut context, instructions[0].clone(), &signers).await.unwrap();
+
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    for (recipient, payment_stream) in recipients.iter().zip(&stream_accounts) {
+        withdraw_lamports(&mut context, &program_id, recipient, &payment_stream.pubkey(), TEST_AMOUNT_PER_INTERVAL)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_batch_initialize_streams_fails_atomically() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let now = current_unix_timestamp(&mut context).await;
+    let stream_accounts = [Keypair::new(), Keypair::new()];
+
+    // The second stream starts in the past, so neither is created
+    let streams = [
+        (stream_accounts[0].pubkey(), stream_entry(&Pubkey::new_unique(), now + 100)),
+        (stream_accounts[1].pubkey(), stream_entry(&Pubkey::new_unique(), now - 1)),
+    ];
+    let instructions = batch_initialize_streams(&program_id, &payer.pubkey(), &streams, None);
+    let result = process_instruction(
+        &mut context,
+        instructions[0].clone(),
+        &[&payer, &stream_accounts[0], &stream_accounts[1]],
+    )
+    .await;
+    assert_program_error(result, PaymentError::InvalidStartTime.into());
+
+    for payment_stream in &stream_accounts {
+        let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap();
+        assert!(account.is_none());
+    }
+}
+
+#[test]
+fn test_batch_initialize_streams_splits_large_batches() {
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let streams: Vec<(Pubkey, StreamEntry)> = (0..40)
+        .map(|_| (Pubkey::new_unique(), stream_entry(&Pubkey::new_unique(), 1_000)))
+        .collect();
+
+    let instructions = batch_initialize_streams(&program_id, &payer, &streams, None);
+    assert!(instructions.len() > 1);
+
+    // Every stream lands in exactly one transaction-sized batch, in order
+    let mut batched = Vec::new();
+    for instruction in &instructions {
+        let message = Message::new(std::slice::from_ref(instruction), Some(&payer));
+        let size = 1 + 64 * usize::from(message.header.num_required_signatures) + message.serialize().len();
+        assert!(size <= MAX_TRANSACTION_SIZE);
+
+        match StreamPayInstruction::unpack(&instruction.data).unwrap() {
+            StreamPayInstruction::BatchInitializeStreams { streams, mint: None } => {
+                assert!(streams.len() <= MAX_BATCH_STREAMS);
+                batched.extend(streams);
+            }
+            instruction => panic!("unexpected instruction: {:?}", instruction),
+        }
+    }
+    let entries: Vec<StreamEntry> = streams.into_iter().map(|(_, entry)| entry).collect();
+    assert_eq!(batched, entries);
+}
+
+// Withdraw Max Test Cases
+// -----------------------
+
+#[tokio::test]
+async fn test_withdraw_max_takes_everything_vested() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    let mut instruction = withdraw_instruction(&program_id, &recipient.pubkey(), true, &payment_stream.pubkey(), 0);
+    instruction.data = StreamPayInstruction::WithdrawMax.pack();
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    process_instruction(&mut context, instruction.clone(), &[&recipient]).await.unwrap();
+
+    // The context payer covers transaction fees, so the balance only moves by the payout
+    let recipient_gain = context.banks_client.get_balance(recipient.pubkey()).await.unwrap() - recipient_balance;
+    assert_eq!(recipient_gain, TEST_AMOUNT_PER_INTERVAL);
+
+    // With nothing left to withdraw there is nothing to take
+    let result = process_instruction(&mut context, instruction, &[&recipient]).await;
+    assert_program_error(result, PaymentError::InsufficientFunds.into());
+}
+
+#[tokio::test]
+async fn test_withdraw_to_named_destination() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let destination = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    let mut instruction = withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    instruction.accounts.push(AccountMeta::new(destination.pubkey(), false));
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    let destination_balance = context.banks_client.get_balance(destination.pubkey()).await.unwrap();
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+
+    let destination_gain = context.banks_client.get_balance(destination.pubkey()).await.unwrap() - destination_balance;
+    assert_eq!(destination_gain, TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(context.banks_client.get_balance(recipient.pubkey()).await.unwrap(), recipient_balance);
+}
+
+// Crank Withdrawal Test Cases
+// ---------------------------
+
+/// Builds a lamport `CrankWithdraw` paying `recipient`, triggered by `keeper`.
+fn crank_withdraw_instruction(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    payment_stream: &Pubkey,
+    keeper: &Pubkey,
+    keeper_is_signer: bool,
+) -> Instruction {
+    let mut instruction = withdraw_instruction(program_id, recipient, false, payment_stream, 0);
+    instruction.data = StreamPayInstruction::CrankWithdraw.pack();
+    instruction.accounts.push(AccountMeta::new(*keeper, keeper_is_signer));
+    instruction
+}
+
+#[tokio::test]
+async fn test_crank_withdraw_pays_recipient_and_tips_keeper() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let admin = context.payer.insecure_clone();
+    let mut params = config_params(&context, 0, FeeChargePoint::Deposit);
+    params.crank_tip_bps = 100;
+    update_config(&mut context, &program_id, &admin, params).await.unwrap();
+
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let keeper = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        deposit: Some(TEST_MAX_WITHDRAWAL_AMOUNT),
+        allow_crank: true,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    warp_to_timestamp(&mut context, start_time + MIN_CRANK_INTERVAL).await;
+
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    let keeper_balance = context.banks_client.get_balance(keeper.pubkey()).await.unwrap();
+    let instruction = crank_withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &keeper.pubkey(),
+        true,
+    );
+    process_instruction(&mut context, instruction, &[&keeper]).await.unwrap();
+
+    // The keeper takes its 1% tip out of everything vested, the recipient the rest
+    let tip = TEST_MAX_WITHDRAWAL_AMOUNT / 100;
+    let keeper_gain = context.banks_client.get_balance(keeper.pubkey()).await.unwrap() - keeper_balance;
+    let recipient_gain = context.banks_client.get_balance(recipient.pubkey()).await.unwrap() - recipient_balance;
+    assert_eq!(keeper_gain, tip);
+    assert_eq!(recipient_gain, TEST_MAX_WITHDRAWAL_AMOUNT - tip);
+}
+
+#[tokio::test]
+async fn test_crank_withdraw_needs_opt_in_and_keeper_signature() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let keeper = create_recipient(&mut context).await;
+
+    // Without the opt-in no keeper may crank, and with it the keeper must sign
+    for allow_crank in [false, true] {
+        let start_time = current_unix_timestamp(&mut context).await + 100;
+        let options = StreamOptions {
+            allow_crank,
+            ..StreamOptions::default()
+        };
+        let payment_stream =
+            initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+                .await
+                .unwrap();
+        warp_to_timestamp(&mut context, start_time + MIN_CRANK_INTERVAL).await;
+
+        let instruction = crank_withdraw_instruction(
+            &program_id,
+            &recipient.pubkey(),
+            &payment_stream.pubkey(),
+            &keeper.pubkey(),
+            false,
+        );
+        let result = process_instruction(&mut context, instruction, &[]).await;
+        let expected = match allow_crank {
+            false => PaymentError::CrankNotAllowed.into(),
+            true => ProgramError::MissingRequiredSignature,
+        };
+        assert_program_error(result, expected);
+    }
+}
+
+#[tokio::test]
+async fn test_crank_withdraw_waits_for_crank_interval() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let keeper = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        deposit: Some(2 * TEST_MINIMUM_DEPOSIT),
+        allow_crank: true,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    let instruction = crank_withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &keeper.pubkey(),
+        true,
+    );
+
+    // Funds have vested, but the stream only started a moment ago
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let result = process_instruction(&mut context, instruction.clone(), &[&keeper]).await;
+    assert_program_error(result, PaymentError::CrankTooSoon.into());
+
+    // The recipient's own withdrawal restarts the wait
+    withdraw_lamports(&mut context, &program_id, &recipient, &payment_stream.pubkey(), TEST_MINIMUM_DEPOSIT)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + MIN_CRANK_INTERVAL).await;
+    let result = process_instruction(&mut context, instruction.clone(), &[&keeper]).await;
+    assert_program_error(result, PaymentError::CrankTooSoon.into());
+
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp + MIN_CRANK_INTERVAL).await;
+    process_instruction(&mut context, instruction, &[&keeper]).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_crank_withdraw_paces_each_split_recipient() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let first = create_recipient(&mut context).await;
+    let second = create_recipient(&mut context).await;
+    let keeper = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        deposit: Some(2 * TEST_MINIMUM_DEPOSIT),
+        allow_crank: true,
+        splits: vec![
+            SplitRecipient::new(first.pubkey(), 6_000),
+            SplitRecipient::new(second.pubkey(), 4_000),
+        ],
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &first.pubkey(), start_time, options)
+            .await
+            .unwrap();
+
+    // The first recipient's own withdrawal restarts only its own wait
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    withdraw_lamports(&mut context, &program_id, &first, &payment_stream.pubkey(), 3).await.unwrap();
+    warp_to_timestamp(&mut context, start_time + MIN_CRANK_INTERVAL).await;
+    let crank_first =
+        crank_withdraw_instruction(&program_id, &first.pubkey(), &payment_stream.pubkey(), &keeper.pubkey(), true);
+    let result = process_instruction(&mut context, crank_first.clone(), &[&keeper]).await;
+    assert_program_error(result, PaymentError::CrankTooSoon.into());
+    let crank_second =
+        crank_withdraw_instruction(&program_id, &second.pubkey(), &payment_stream.pubkey(), &keeper.pubkey(), true);
+    process_instruction(&mut context, crank_second, &[&keeper]).await.unwrap();
+
+    // Cranking the second recipient does not hold back the first
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp + MIN_CRANK_INTERVAL).await;
+    process_instruction(&mut context, crank_first, &[&keeper]).await.unwrap();
+}
+
+// Payout Destination Test Cases
+// -----------------------------
+
+/// Builds a `SetPayoutDestination` pointing `recipient`'s withdrawals at `destination`.
+fn set_payout_destination_instruction(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    payment_stream: &Pubkey,
+    destination: &Pubkey,
+) -> Instruction {
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::SetPayoutDestination.pack(),
+        vec![
+            AccountMeta::new_readonly(*recipient, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new_readonly(*destination, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_payout_destination_receives_withdrawals_and_cranks() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let destination = create_recipient(&mut context).await;
+    let keeper = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        deposit: Some(2 * TEST_MINIMUM_DEPOSIT),
+        allow_crank: true,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+    let instruction = set_payout_destination_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &destination.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    // The recipient can no longer be paid itself or name another destination
+    let withdraw = withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+        TEST_MINIMUM_DEPOSIT,
+    );
+    let result = process_instruction(&mut context, withdraw.clone(), &[&recipient]).await;
+    assert_program_error(result, PaymentError::InvalidPayoutDestination.into());
+    let mut to_other = withdraw.clone();
+    to_other.accounts.push(AccountMeta::new(keeper.pubkey(), false));
+    let result = process_instruction(&mut context, to_other, &[&recipient]).await;
+    assert_program_error(result, PaymentError::InvalidPayoutDestination.into());
+
+    let destination_balance = context.banks_client.get_balance(destination.pubkey()).await.unwrap();
+    let mut to_destination = withdraw;
+    to_destination.accounts.push(AccountMeta::new(destination.pubkey(), false));
+    process_instruction(&mut context, to_destination, &[&recipient]).await.unwrap();
+
+    // A keeper has to pay the same destination
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp + MIN_CRANK_INTERVAL).await;
+    let mut crank = crank_withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &keeper.pubkey(),
+        true,
+    );
+    let result = process_instruction(&mut context, crank.clone(), &[&keeper]).await;
+    assert_program_error(result, PaymentError::InvalidPayoutDestination.into());
+    crank.accounts.push(AccountMeta::new(destination.pubkey(), false));
+    process_instruction(&mut context, crank, &[&keeper]).await.unwrap();
+
+    let destination_gain = context.banks_client.get_balance(destination.pubkey()).await.unwrap() - destination_balance;
+    assert_eq!(destination_gain, 2 * TEST_MINIMUM_DEPOSIT);
+}
+
+#[tokio::test]
+async fn test_payout_destination_is_set_by_the_recipient() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let destination = Pubkey::new_unique();
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream_with(
+        &mut context,
+        &program_id,
+        &payer,
+        &recipient.pubkey(),
+        start_time,
+        StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // Not even the payer can redirect the recipient's payouts
+    let instruction =
+        set_payout_destination_instruction(&program_id, &payer.pubkey(), &payment_stream.pubkey(), &destination);
+    let result = process_instruction(&mut context, instruction, &[&payer]).await;
+    assert_program_error(result, PaymentError::InvalidRecipient.into());
+
+    let instruction =
+        set_payout_destination_instruction(&program_id, &recipient.pubkey(), &payment_stream.pubkey(), &destination);
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    assert_eq!(PaymentStream::unpack(&account.data).unwrap().payout_destination, Some(destination));
+
+    // Naming the recipient itself clears it
+    let instruction = set_payout_destination_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &recipient.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    assert_eq!(PaymentStream::unpack(&account.data).unwrap().payout_destination, None);
+}
+
+#[tokio::test]
+async fn test_payout_destination_receives_termination_payout() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let destination = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream_with(
+        &mut context,
+        &program_id,
+        &payer,
+        &recipient.pubkey(),
+        start_time,
+        StreamOptions::default(),
+    )
+    .await
+    .unwrap();
+    let instruction = set_payout_destination_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &destination.pubkey(),
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    // The payer cannot terminate the stream without paying the payout destination
+    let terminate = terminate_instruction(
+        &program_id,
+        &payer.pubkey(),
+        true,
+        &recipient.pubkey(),
+        false,
+        &payment_stream.pubkey(),
+    );
+    let result = process_instruction(&mut context, terminate.clone(), &[&payer]).await;
+    assert_program_error(result, PaymentError::InvalidPayoutDestination.into());
+    let mut to_other = terminate.clone();
+    to_other.accounts.push(AccountMeta::new(recipient.pubkey(), false));
+    let result = process_instruction(&mut context, to_other, &[&payer]).await;
+    assert_program_error(result, PaymentError::InvalidPayoutDestination.into());
+
+    let recipient_balance = context.banks_client.get_balance(recipient.pubkey()).await.unwrap();
+    let destination_balance = context.banks_client.get_balance(destination.pubkey()).await.unwrap();
+    let mut to_destination = terminate;
+    to_destination.accounts.push(AccountMeta::new(destination.pubkey(), false));
+    process_instruction(&mut context, to_destination, &[&payer]).await.unwrap();
+
+    let destination_gain = context.banks_client.get_balance(destination.pubkey()).await.unwrap() - destination_balance;
+    assert_eq!(destination_gain, TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(context.banks_client.get_balance(recipient.pubkey()).await.unwrap(), recipient_balance);
+}
+
+// Event Test Cases
+// ----------------
+
+#[tokio::test]
+async fn test_withdraw_emits_decodable_event() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+
+    let instruction = withdraw_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        true,
+        &payment_stream.pubkey(),
+        TEST_AMOUNT_PER_INTERVAL,
+    );
+    let payer = context.payer.insecure_clone();
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[&payer, &recipient],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    let result = context.banks_client.process_transaction_with_metadata(transaction).await.unwrap();
+    assert!(result.result.is_ok());
+
+    // Indexers only need the transaction's logs to follow the stream
+    let events = StreamEvent::decode_logs(&result.metadata.unwrap().log_messages).unwrap();
+    assert_eq!(
+        events,
+        vec![StreamEvent::Withdrawn {
+            payment_stream: payment_stream.pubkey(),
+            recipient: recipient.pubkey(),
+            destination: recipient.pubkey(),
+            amount: TEST_AMOUNT_PER_INTERVAL,
+            fee: 0,
+            keeper: None,
+            tip: 0,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_transfer_recipient_emits_event() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let new_recipient = Pubkey::new_unique();
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let options = StreamOptions {
+        transferable_by_recipient: true,
+        ..StreamOptions::default()
+    };
+    let payment_stream =
+        initialize_lamport_stream_with(&mut context, &program_id, &payer, &recipient.pubkey(), start_time, options)
+            .await
+            .unwrap();
+
+    let instruction =
+        transfer_recipient_instruction(&program_id, &recipient.pubkey(), &payment_stream.pubkey(), &new_recipient);
+    let events = process_instruction_events(&mut context, instruction, &[&recipient]).await;
+    assert_eq!(
+        events,
+        vec![StreamEvent::RecipientTransferred {
+            payment_stream: payment_stream.pubkey(),
+            transferred_by: recipient.pubkey(),
+            old_recipient: recipient.pubkey(),
+            new_recipient,
+        }]
+    );
+}
+
+#[tokio::test]
+async fn test_update_blackout_windows_emits_event() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let roles = stream_roles(&mut context, program_id).await;
+    let start = current_unix_timestamp(&mut context).await + 1_000;
+    let blackout_windows = vec![BlackoutWindow {
+        start,
+        end: start + TEST_INTERVAL as UnixTimestamp,
+    }];
+
+    let instruction = stream_authority_instruction(
+        &roles.program_id,
+        StreamPayInstruction::UpdateBlackoutWindows {
+            blackout_windows: blackout_windows.clone(),
+        },
+        &roles.delegate.pubkey(),
+        true,
+        &roles.payment_stream,
+    );
+    let events = process_instruction_events(&mut context, instruction, &[&roles.delegate]).await;
+    assert_eq!(
+        events,
+        vec![StreamEvent::BlackoutWindowsUpdated {
+            payment_stream: roles.payment_stream,
+            blackout_windows,
+        }]
+    );
+}
+
+// Query Test Cases
+// ----------------
+
+#[tokio::test]
+async fn test_query_stream_returns_snapshot() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+    let now = start_time + TEST_INTERVAL as UnixTimestamp;
+    warp_to_timestamp(&mut context, now).await;
+
+    // Anyone can read the stream, so no signer but the fee payer is needed
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &StreamPayInstruction::QueryStream.pack(),
+        vec![AccountMeta::new_readonly(payment_stream.pubkey(), false)],
+    );
+    let payer = context.payer.insecure_clone();
+    let transaction = Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[&payer],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    let simulation = context.banks_client.simulate_transaction(transaction).await.unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+
+    assert_eq!(return_data.program_id, program_id);
+    let snapshot = StreamSnapshot::try_from_slice(&return_data.data).unwrap();
+    assert_eq!(snapshot.timestamp, now);
+    assert_eq!(snapshot.status, StreamStatus::Active);
+    assert_eq!(snapshot.vested_amount, TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(snapshot.withdrawable_amount, TEST_AMOUNT_PER_INTERVAL);
+}
+
+// Account Layout Test Cases
+// -------------------------
+
+#[tokio::test]
+async fn test_stream_instructions_reject_other_program_accounts() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let recipient = create_recipient(&mut context).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_lamport_stream(&mut context, &program_id, &recipient.pubkey(), start_time)
+        .await
+        .unwrap();
+
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), PaymentStream::LEN);
+    assert_eq!(account.data[..8], PaymentStream::DISCRIMINATOR);
+
+    // The config PDA is owned by the program too, but is no payment stream
+    let (config, _) = find_config_address(&program_id);
+    let instruction = withdraw_instruction(&program_id, &recipient.pubkey(), true, &config, TEST_AMOUNT_PER_INTERVAL);
+    let result = process_instruction(&mut context, instruction, &[&recipient]).await;
+    assert_program_error(result, PaymentError::InvalidAccountType.into());
+}
+
+#[tokio::test]
+async fn test_config_account_is_tagged_and_versioned() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+
+    let (config, _) = find_config_address(&program_id);
+    let account = context.banks_client.get_account(config).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), ProgramConfig::LEN);
+    assert_eq!(account.data[..8], ProgramConfig::DISCRIMINATOR);
+    assert_eq!(account.data[8], ProgramConfig::VERSION);
+    assert!(ProgramConfig::unpack(&account.data).unwrap().is_initialized);
+    assert!(account.data[ProgramConfig::LEN - ProgramConfig::RESERVED_LEN..].iter().all(|byte| *byte == 0));
+
+    // Neither kind of account reads as the other
+    assert_eq!(PaymentStream::unpack(&account.data), Err(PaymentError::InvalidAccountType.into()));
+    let mut stream_data = vec![0u8; PaymentStream::LEN];
+    PaymentStream::new(Pubkey::new_unique(), Pubkey::new_unique()).pack(&mut stream_data).unwrap();
+    assert_eq!(ProgramConfig::unpack(&stream_data), Err(PaymentError::InvalidAccountType.into()));
+}
+
+// Stream Migration Test Cases
+// ---------------------------
+
+#[tokio::test]
+async fn test_migrate_stream_upgrades_v1_account() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    let rent = context.banks_client.get_rent().await.unwrap();
+
+    // A stream account left behind by layout version 1
+    let v1_data = include_bytes!("fixtures/payment_stream_v1_lamport.bin");
+    let payment_stream = Pubkey::new_unique();
+    let mut account = AccountSharedData::new(rent.minimum_balance(v1_data.len()), v1_data.len(), &program_id);
+    account.set_data_from_slice(v1_data);
+    context.set_account(&payment_stream, &account);
+
+    let payer = create_recipient(&mut context).await;
+    let payer_balance = context.banks_client.get_balance(payer.pubkey()).await.unwrap();
+    let instruction = Instruction::new_with_bytes(
+        program_id,
+        &StreamPayInstruction::MigrateStream.pack(),
+        vec![
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new(payment_stream, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+    );
+    let events = process_instruction_events(&mut context, instruction.clone(), &[&payer]).await;
+    assert_eq!(
+        events,
+        vec![StreamEvent::Migrated {
+            payment_stream,
+            from_version: 1,
+            to_version: PaymentStream::VERSION,
+        }]
+    );
+
+    // Version 4 outgrew the reserved space, so the account grows and the signer
+    // pays the extra rent
+    let account = context.banks_client.get_account(payment_stream).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), PaymentStream::LEN);
+    assert_eq!(account.lamports, rent.minimum_balance(PaymentStream::LEN));
+    let rent_paid = payer_balance - context.banks_client.get_balance(payer.pubkey()).await.unwrap();
+    assert_eq!(rent_paid, rent.minimum_balance(PaymentStream::LEN) - rent.minimum_balance(v1_data.len()));
+
+    let (expected_stream, _) = PaymentStream::unpack_any_version(v1_data).unwrap();
+    assert_eq!(PaymentStream::unpack(&account.data).unwrap(), expected_stream);
+
+    // Migrating a stream already on the current layout changes nothing
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+    let migrated_account = context.banks_client.get_account(payment_stream).await.unwrap().unwrap();
+    assert_eq!(migrated_account, account);
+}
+
+// SPL Token Stream Test Cases
+// ---------------------------
+
+const TEST_TOKEN_DEPOSIT: u64 = 1_000;
+
+/// Creates a mint owned by `token_program` with the context payer as mint authority.
+async fn create_mint(context: &mut ProgramTestContext, token_program: &Pubkey) -> Pubkey {
+    let mint = Keypair::new();
+    let space = Mint::LEN;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &mint.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                token_program,
+            ),
+            spl_token_2022::instruction::initialize_mint2(
+                token_program,
+                &mint.pubkey(),
+                &context.payer.pubkey(),
+                None,
+                0,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, &mint],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await.unwrap();
+    mint.pubkey()
+}
+
+/// Creates a Token-2022 mint that withholds `transfer_fee_bps` of every transfer,
+/// with the context payer as mint authority.
+async fn create_transfer_fee_mint(context: &mut ProgramTestContext, transfer_fee_bps: u16) -> Pubkey {
+    let mint = Keypair::new();
+    let space = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::TransferFeeConfig]).unwrap();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &mint.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                &spl_token_2022::id(),
+            ),
+            initialize_transfer_fee_config(
+                &spl_token_2022::id(),
+                &mint.pubkey(),
+                Some(&context.payer.pubkey()),
+                Some(&context.payer.pubkey()),
+                transfer_fee_bps,
+                u64::MAX,
+            )
+            .unwrap(),
+            spl_token_2022::instruction::initialize_mint2(
+                &spl_token_2022::id(),
+                &mint.pubkey(),
+                &context.payer.pubkey(),
+                None,
+                0,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, &mint],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await.unwrap();
+    mint.pubkey()
+}
+
+/// Creates `owner`'s associated token account for `mint` and mints `amount` into it.
+async fn create_token_account(
+    context: &mut ProgramTestContext,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Pubkey {
+    let token_account = get_associated_token_address_with_program_id(owner, mint, token_program);
+    let mut instructions = vec![create_associated_token_account(
+        &context.payer.pubkey(),
+        owner,
+        mint,
+        token_program,
+    )];
+    if amount > 0 {
+        instructions.push(
+            spl_token_2022::instruction::mint_to(
+                token_program,
+                mint,
+                &token_account,
+                &context.payer.pubkey(),
+                &[],
+                amount,
+            )
+            .unwrap(),
+        );
+    }
+    let transaction = Transaction::new_signed_with_payer(
+        &instructions,
+        Some(&context.payer.pubkey()),
+        &[&context.payer],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await.unwrap();
+    token_account
+}
+
+async fn get_token_balance(context: &mut ProgramTestContext, token_account: &Pubkey) -> u64 {
+    let account = context.banks_client.get_account(*token_account).await.unwrap().unwrap();
+    StateWithExtensions::<TokenAccount>::unpack(&account.data).unwrap().base.amount
+}
+
+/// A one-interval stepped token stream to `recipient`, releasing `TEST_TOKEN_DEPOSIT`.
+fn token_stream_entry(recipient: &Pubkey, start_time: UnixTimestamp) -> StreamEntry {
+    StreamEntry {
+        recipient: *recipient,
+        start_time: start_time as u64,
+        interval: TEST_INTERVAL,
+        amount_per_interval: TEST_TOKEN_DEPOSIT,
+        total_amount: TEST_TOKEN_DEPOSIT,
+        cliff_time: 0,
+        cliff_amount: 0,
+        end_time: 0,
+        release_curve: ReleaseCurve::Stepped,
+    }
+}
+
+/// Creates the stream account and initializes a token stream of `mint` with the
+/// schedule in `entry`, funded from `payer`'s token account.
+async fn initialize_token_stream(
+    context: &mut ProgramTestContext,
+    program_id: &Pubkey,
+    payer: &Keypair,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    entry: StreamEntry,
+) -> Result<Keypair, BanksClientError> {
+    let payment_stream = Keypair::new();
+    let space = PaymentStream::LEN;
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let (vault, _) = find_vault_address(&payment_stream.pubkey(), program_id);
+    let (treasury, _) = find_treasury_address(program_id);
+    let token_account = |owner: &Pubkey| get_associated_token_address_with_program_id(owner, mint, token_program);
+
+    let data = StreamPayInstruction::InitializeStream {
+        start_time: entry.start_time,
+        interval: entry.interval,
+        amount_per_interval: entry.amount_per_interval,
+        total_amount: entry.total_amount,
+        cliff_time: entry.cliff_time,
+        cliff_amount: entry.cliff_amount,
+        end_time: entry.end_time,
+        release_curve: entry.release_curve,
+        delegate: None,
+        cancellation_policy: CancellationPolicy::PayerOnly,
+        cancellation_penalty_bps: 0,
+        transferable_by_recipient: false,
+        transferable_by_sender: false,
+        update_requires_recipient_consent: false,
+        allow_crank: false,
+        blackout_windows: Vec::new(),
+        splits: Vec::new(),
+        mint: Some(*mint),
+    }
+    .pack();
+    let transaction = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &context.payer.pubkey(),
+                &payment_stream.pubkey(),
+                rent.minimum_balance(space),
+                space as u64,
+                program_id,
+            ),
+            Instruction::new_with_bytes(
+                *program_id,
+                &data,
+                vec![
+                    AccountMeta::new(payer.pubkey(), true),
+                    AccountMeta::new(payment_stream.pubkey(), false),
+                    AccountMeta::new_readonly(entry.recipient, false),
+                    AccountMeta::new(vault, false),
+                    AccountMeta::new_readonly(find_config_address(program_id).0, false),
+                    AccountMeta::new(treasury, false),
+                    AccountMeta::new_readonly(system_program::id(), false),
+                    AccountMeta::new_readonly(*mint, false),
+                    AccountMeta::new(token_account(&vault), false),
+                    AccountMeta::new_readonly(*token_program, false),
+                    AccountMeta::new(token_account(&payer.pubkey()), false),
+                    AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+                    AccountMeta::new(token_account(&treasury), false),
+                ],
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+        &[&context.payer, payer, &payment_stream],
+        context.get_new_latest_blockhash().await.unwrap(),
+    );
+    context.banks_client.process_transaction(transaction).await?;
+
+    Ok(payment_stream)
+}
+
+/// Builds a token `Withdraw` signed by `recipient`, paying into its associated token account.
+fn withdraw_tokens_instruction(
+    program_id: &Pubkey,
+    recipient: &Pubkey,
+    payment_stream: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    let (treasury, _) = find_treasury_address(program_id);
+    let token_account = |owner: &Pubkey| get_associated_token_address_with_program_id(owner, mint, token_program);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::Withdraw { amount }.pack(),
+        vec![
+            AccountMeta::new(*recipient, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(treasury, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(token_account(&vault), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new(token_account(recipient), false),
+            AccountMeta::new(token_account(&treasury), false),
+        ],
+    )
+}
+
+/// Builds a token `TopUp` of `amount`, funded from `funder`'s associated token account.
+fn top_up_tokens_instruction(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    payment_stream: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    let (treasury, _) = find_treasury_address(program_id);
+    let token_account = |owner: &Pubkey| get_associated_token_address_with_program_id(owner, mint, token_program);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::TopUp { amount }.pack(),
+        vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(treasury, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new(token_account(&vault), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new(token_account(funder), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+            AccountMeta::new(token_account(&treasury), false),
+        ],
+    )
+}
+
+/// Builds a token `TerminateStream` signed by `payer`.
+fn terminate_tokens_instruction(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_stream: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    let (vault, _) = find_vault_address(payment_stream, program_id);
+    let (treasury, _) = find_treasury_address(program_id);
+    let token_account = |owner: &Pubkey| get_associated_token_address_with_program_id(owner, mint, token_program);
+    Instruction::new_with_bytes(
+        *program_id,
+        &StreamPayInstruction::TerminateStream.pack(),
+        vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(*payment_stream, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(vault, false),
+            AccountMeta::new_readonly(find_config_address(program_id).0, false),
+            AccountMeta::new(treasury, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(*mint, false),
+            AccountMeta::new(token_account(&vault), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new(token_account(recipient), false),
+            AccountMeta::new(token_account(payer), false),
+            AccountMeta::new(token_account(&treasury), false),
+        ],
+    )
+}
+
+#[tokio::test]
+async fn test_token_stream_initialize_withdraw_and_terminate() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+
+    for token_program in [spl_token::id(), spl_token_2022::id()] {
+        let mint = create_mint(&mut context, &token_program).await;
+        let payer_tokens =
+            create_token_account(&mut context, &mint, &token_program, &payer.pubkey(), 2 * TEST_TOKEN_DEPOSIT).await;
+        let recipient_tokens = create_token_account(&mut context, &mint, &token_program, &recipient.pubkey(), 0).await;
+
+        let start_time = current_unix_timestamp(&mut context).await + 100;
+        let payment_stream = initialize_token_stream(
+            &mut context,
+            &program_id,
+            &payer,
+            &mint,
+            &token_program,
+            token_stream_entry(&recipient.pubkey(), start_time),
+        )
+        .await
+        .unwrap();
+
+        let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+        let stream = PaymentStream::unpack(&account.data).unwrap();
+        assert!(stream.is_spl_token);
+        assert_eq!(stream.mint, mint);
+        assert_eq!(get_token_balance(&mut context, &payer_tokens).await, TEST_TOKEN_DEPOSIT);
+
+        warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+        let instruction = withdraw_tokens_instruction(
+            &program_id,
+            &recipient.pubkey(),
+            &payment_stream.pubkey(),
+            &mint,
+            &token_program,
+            TEST_TOKEN_DEPOSIT / 2,
+        );
+        process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+        assert_eq!(get_token_balance(&mut context, &recipient_tokens).await, TEST_TOKEN_DEPOSIT / 2);
+
+        // Terminating pays the recipient the rest of what vested and closes the vault
+        let instruction = terminate_tokens_instruction(
+            &program_id,
+            &payer.pubkey(),
+            &recipient.pubkey(),
+            &payment_stream.pubkey(),
+            &mint,
+            &token_program,
+        );
+        process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+        assert_eq!(get_token_balance(&mut context, &recipient_tokens).await, TEST_TOKEN_DEPOSIT);
+        assert_eq!(get_token_balance(&mut context, &payer_tokens).await, TEST_TOKEN_DEPOSIT);
+
+        let (vault, _) = find_vault_address(&payment_stream.pubkey(), &program_id);
+        let vault_tokens = get_associated_token_address_with_program_id(&vault, &mint, &token_program);
+        assert!(context.banks_client.get_account(vault_tokens).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_transfer_fee_token_stream_terminates() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let token_program = spl_token_2022::id();
+    let mint = create_transfer_fee_mint(&mut context, 100).await;
+    create_token_account(&mut context, &mint, &token_program, &payer.pubkey(), TEST_TOKEN_DEPOSIT).await;
+    let recipient_tokens = create_token_account(&mut context, &mint, &token_program, &recipient.pubkey(), 0).await;
+
+    // The 1% transfer fee on the deposit stays withheld in the vault
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_token_stream(
+        &mut context,
+        &program_id,
+        &payer,
+        &mint,
+        &token_program,
+        token_stream_entry(&recipient.pubkey(), start_time),
+    )
+    .await
+    .unwrap();
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    assert_eq!(PaymentStream::unpack(&account.data).unwrap().total_amount, 990);
+
+    warp_to_timestamp(&mut context, start_time + TEST_INTERVAL as UnixTimestamp).await;
+    let instruction = withdraw_tokens_instruction(
+        &program_id,
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &mint,
+        &token_program,
+        495,
+    );
+    process_instruction(&mut context, instruction, &[&recipient]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &recipient_tokens).await, 490);
+
+    // Terminating harvests the vault's withheld fees to the mint, so the vault can close
+    let instruction = terminate_tokens_instruction(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        &payment_stream.pubkey(),
+        &mint,
+        &token_program,
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+    assert_eq!(get_token_balance(&mut context, &recipient_tokens).await, 980);
+
+    let (vault, _) = find_vault_address(&payment_stream.pubkey(), &program_id);
+    let vault_tokens = get_associated_token_address_with_program_id(&vault, &mint, &token_program);
+    assert!(context.banks_client.get_account(vault_tokens).await.unwrap().is_none());
+
+    let mint_account = context.banks_client.get_account(mint).await.unwrap().unwrap();
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_account.data).unwrap();
+    let transfer_fee_config = mint_state.get_extension::<TransferFeeConfig>().unwrap();
+    assert_eq!(u64::from(transfer_fee_config.withheld_amount), 10);
+}
+
+#[tokio::test]
+async fn test_transfer_fee_token_stream_schedule_fits_credited_amount() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let token_program = spl_token_2022::id();
+    let mint = create_transfer_fee_mint(&mut context, 100).await;
+    create_token_account(&mut context, &mint, &token_program, &payer.pubkey(), 2 * TEST_TOKEN_DEPOSIT).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let breakpoints = |cumulative_amount| {
+        ReleaseCurve::Breakpoints(vec![Breakpoint {
+            timestamp: start_time + TEST_INTERVAL as UnixTimestamp,
+            cumulative_amount,
+        }])
+    };
+
+    // The vault is credited 990 of the 1000 deposited, so a curve ending at 1000 over-promises
+    let entry = StreamEntry {
+        release_curve: breakpoints(TEST_TOKEN_DEPOSIT),
+        ..token_stream_entry(&recipient.pubkey(), start_time)
+    };
+    let result = initialize_token_stream(&mut context, &program_id, &payer, &mint, &token_program, entry).await;
+    assert_program_error(result.map(|_| ()), PaymentError::InvalidReleaseCurve.into());
+
+    let entry = StreamEntry {
+        release_curve: breakpoints(990),
+        ..token_stream_entry(&recipient.pubkey(), start_time)
+    };
+    initialize_token_stream(&mut context, &program_id, &payer, &mint, &token_program, entry)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_transfer_fee_token_stream_top_up_credits_net_amount() {
+    let program_id = Pubkey::new_unique();
+    let mut context = program_test(program_id).start_with_context().await;
+    initialize_config(&mut context, &program_id, 0, FeeChargePoint::Deposit).await;
+    let payer = create_recipient(&mut context).await;
+    let recipient = create_recipient(&mut context).await;
+    let token_program = spl_token_2022::id();
+    let mint = create_transfer_fee_mint(&mut context, 100).await;
+    create_token_account(&mut context, &mint, &token_program, &payer.pubkey(), 2 * TEST_TOKEN_DEPOSIT).await;
+    let start_time = current_unix_timestamp(&mut context).await + 100;
+    let payment_stream = initialize_token_stream(
+        &mut context,
+        &program_id,
+        &payer,
+        &mint,
+        &token_program,
+        token_stream_entry(&recipient.pubkey(), start_time),
+    )
+    .await
+    .unwrap();
+
+    // Only the 990 that reach the vault after the 1% transfer fee are added
+    let instruction = top_up_tokens_instruction(
+        &program_id,
+        &payer.pubkey(),
+        &payment_stream.pubkey(),
+        &mint,
+        &token_program,
+        TEST_TOKEN_DEPOSIT,
+    );
+    process_instruction(&mut context, instruction, &[&payer]).await.unwrap();
+
+    let account = context.banks_client.get_account(payment_stream.pubkey()).await.unwrap().unwrap();
+    assert_eq!(PaymentStream::unpack(&account.data).unwrap().total_amount, 2 * 990);
+    let (vault, _) = find_vault_address(&payment_stream.pubkey(), &program_id);
+    let vault_tokens = get_associated_token_address_with_program_id(&vault, &mint, &token_program);
+    assert_eq!(get_token_balance(&mut context, &vault_tokens).await, 2 * 990);
+}
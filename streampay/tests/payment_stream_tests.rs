@@ -1,15 +1,23 @@
+use borsh::BorshSerialize;
 use solana_program_test::*;
 use solana_sdk::{
     signature::{Keypair, Signer},
     transaction::Transaction,
 };
-use crate::payment_stream::PaymentStream;
+use solana_program::{clock::UnixTimestamp, program_error::ProgramError, pubkey::Pubkey};
+use crate::payment_stream::{
+    BlackoutWindow, Breakpoint, CancellationPolicy, PaymentStream, ReleaseCurve, SplitRecipient, StreamParty,
+    StreamSnapshot, StreamStatus,
+};
+use crate::constants::{MAX_BLACKOUT_WINDOWS, MAX_BREAKPOINTS, MAX_SPLIT_RECIPIENTS};
+use crate::utils::calculate_fee;
 use crate::error::PaymentError;
 
 // Constants for testing
 const TEST_START_TIME: u64 = 100;
 const TEST_INTERVAL: u64 = 10;
 const TEST_AMOUNT_PER_INTERVAL: u64 = 5;
+const TEST_FEE_BPS: u16 = 150; // 1.5%
 
 #[tokio::test]
 async fn test_payment_stream_creation() {
@@ -33,20 +41,960 @@ async fn test_payment_stream_termination() {
 
 #[tokio::test]
 async fn test_payment_stream_fee_calculation() {
-    let payment_stream = PaymentStream::new(TEST_START_TIME, TEST_INTERVAL, TEST_AMOUNT_PER_INTERVAL);
-    let fee = payment_stream.calculate_fee(TEST_AMOUNT_PER_INTERVAL);
-    
-    assert_eq!(fee, (TEST_AMOUNT_PER_INTERVAL as f64 * 0.015) as u64);
+    let fee = calculate_fee(TEST_AMOUNT_PER_INTERVAL, TEST_FEE_BPS).unwrap();
+
+    // 1.5% of 5 is 0.075, which rounds up to a whole unit
+    assert_eq!(fee, 1);
 }
 
-#[tokio::test]
-async fn test_payment_stream_pack_unpack() {
-    let payment_stream = PaymentStream::new(TEST_START_TIME, TEST_INTERVAL, TEST_AMOUNT_PER_INTERVAL);
+#[test]
+fn test_payment_stream_pack_unpack() {
+    let payment_stream = interval_stream();
     let mut packed_data = vec![0u8; PaymentStream::LEN];
-    PaymentStream::pack_into_slice(&payment_stream, &mut packed_data);
-    
-    let unpacked_stream = PaymentStream::unpack_from_slice(&packed_data).unwrap();
+    payment_stream.pack(&mut packed_data).unwrap();
+
+    assert_eq!(packed_data[..8], PaymentStream::DISCRIMINATOR);
+    assert_eq!(packed_data[8], PaymentStream::VERSION);
+    let unpacked_stream = PaymentStream::unpack(&packed_data).unwrap();
     assert_eq!(unpacked_stream, payment_stream);
 }
 
+#[test]
+fn test_largest_stream_fits_account() {
+    let mut payment_stream = PaymentStream::new(Pubkey::new_unique(), Pubkey::new_unique());
+    payment_stream.delegate = Some(Pubkey::new_unique());
+    payment_stream.release_curve = ReleaseCurve::Breakpoints(vec![
+        Breakpoint { timestamp: 0, cumulative_amount: 0 };
+        MAX_BREAKPOINTS
+    ]);
+    payment_stream.blackout_windows = vec![BlackoutWindow { start: 0, end: 0 }; MAX_BLACKOUT_WINDOWS];
+    payment_stream.splits = vec![SplitRecipient::new(Pubkey::new_unique(), 0); MAX_SPLIT_RECIPIENTS];
+    payment_stream.payout_destination = Some(Pubkey::new_unique());
+
+    assert_eq!(payment_stream.try_to_vec().unwrap().len(), PaymentStream::MAX_DATA_LEN);
+    let mut packed_data = vec![0u8; PaymentStream::LEN];
+    payment_stream.pack(&mut packed_data).unwrap();
+    assert_eq!(PaymentStream::unpack(&packed_data).unwrap(), payment_stream);
+
+    // Only the reserved padding is left over
+    assert!(packed_data[PaymentStream::LEN - PaymentStream::RESERVED_LEN..].iter().all(|byte| *byte == 0));
+    assert_eq!(
+        payment_stream.pack(&mut packed_data[..PaymentStream::LEN - 1]),
+        Err(ProgramError::AccountDataTooSmall)
+    );
+}
+
+#[test]
+fn test_unpack_rejects_other_accounts_and_versions() {
+    let mut packed_data = vec![0u8; PaymentStream::LEN];
+    assert_eq!(PaymentStream::unpack(&packed_data), Err(PaymentError::InvalidAccountType.into()));
+
+    interval_stream().pack(&mut packed_data).unwrap();
+    packed_data[8] = PaymentStream::VERSION + 1;
+    assert_eq!(PaymentStream::unpack(&packed_data), Err(PaymentError::UnsupportedAccountVersion.into()));
+
+    packed_data[0] ^= 0xff;
+    assert_eq!(PaymentStream::unpack(&packed_data), Err(PaymentError::InvalidAccountType.into()));
+}
+
+// Vesting Schedule Tests
+// ----------------------
+
+const CLIFF_START: UnixTimestamp = 1_000;
+const CLIFF_TIME: UnixTimestamp = 2_000;
+const CLIFF_END: UnixTimestamp = 6_000;
+const CLIFF_AMOUNT: u64 = 1_000;
+const CLIFF_TOTAL: u64 = 5_000;
+
+/// A stream unlocking 1_000 at the cliff and the remaining 4_000 at one per second.
+fn cliff_stream() -> PaymentStream {
+    let mut payment_stream = PaymentStream::new(Pubkey::new_unique(), Pubkey::new_unique());
+    payment_stream.is_initialized = true;
+    payment_stream.start_time = CLIFF_START;
+    payment_stream.cliff_time = CLIFF_TIME;
+    payment_stream.cliff_amount = CLIFF_AMOUNT;
+    payment_stream.end_time = CLIFF_END;
+    payment_stream.total_amount = CLIFF_TOTAL;
+    payment_stream.release_curve = ReleaseCurve::Linear;
+    payment_stream
+}
+
+/// A stream releasing 5 every 10 seconds out of a 20 deposit.
+fn interval_stream() -> PaymentStream {
+    let mut payment_stream = PaymentStream::new(Pubkey::new_unique(), Pubkey::new_unique());
+    payment_stream.is_initialized = true;
+    payment_stream.start_time = TEST_START_TIME as UnixTimestamp;
+    payment_stream.interval = TEST_INTERVAL;
+    payment_stream.amount_per_interval = TEST_AMOUNT_PER_INTERVAL;
+    payment_stream.total_amount = 4 * TEST_AMOUNT_PER_INTERVAL;
+    payment_stream
+}
+
+#[test]
+fn test_nothing_vests_before_cliff() {
+    let payment_stream = cliff_stream();
+
+    assert_eq!(payment_stream.calculate_vested_amount(0).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME - 1).unwrap(), 0);
+}
+
+#[test]
+fn test_cliff_amount_unlocks_at_cliff() {
+    let payment_stream = cliff_stream();
+
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME).unwrap(), CLIFF_AMOUNT);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 1).unwrap(), CLIFF_AMOUNT + 1);
+}
+
+#[test]
+fn test_linear_vesting_between_cliff_and_end() {
+    let payment_stream = cliff_stream();
+    let midpoint = (CLIFF_TIME + CLIFF_END) / 2;
+
+    assert_eq!(payment_stream.calculate_vested_amount(midpoint).unwrap(), CLIFF_AMOUNT + 2_000);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END - 1).unwrap(), CLIFF_TOTAL - 1);
+}
+
+#[test]
+fn test_everything_vested_at_and_after_end() {
+    let payment_stream = cliff_stream();
+
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END).unwrap(), CLIFF_TOTAL);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END + 1).unwrap(), CLIFF_TOTAL);
+    assert_eq!(payment_stream.calculate_vested_amount(UnixTimestamp::MAX).unwrap(), CLIFF_TOTAL);
+}
+
+#[test]
+fn test_linear_vesting_rounds_down() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.total_amount = CLIFF_AMOUNT + 3;
+
+    // 3 units over 4_000 seconds: the first unit vests after 1_334 seconds
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 1_333).unwrap(), CLIFF_AMOUNT);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 1_334).unwrap(), CLIFF_AMOUNT + 1);
+}
+
+#[test]
+fn test_cliff_at_end_vests_everything_at_once() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.cliff_time = CLIFF_END;
+
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END).unwrap(), CLIFF_TOTAL);
+}
+
+#[test]
+fn test_linear_vesting_without_cliff() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.cliff_time = CLIFF_START;
+    payment_stream.cliff_amount = 0;
+
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START + 1).unwrap(), 1);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END).unwrap(), CLIFF_TOTAL);
+}
+
+#[test]
+fn test_interval_vesting_steps() {
+    let payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    assert_eq!(payment_stream.calculate_vested_amount(start - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(start).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(start + interval - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(start + interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 10 * interval).unwrap(), 4 * TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[test]
+fn test_max_withdrawable_excludes_withdrawn_amount() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.withdrawn_amount = 600;
+
+    assert_eq!(payment_stream.calculate_max_withdrawable(CLIFF_TIME - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_max_withdrawable(CLIFF_TIME).unwrap(), CLIFF_AMOUNT - 600);
+    assert_eq!(payment_stream.calculate_max_withdrawable(CLIFF_END).unwrap(), CLIFF_TOTAL - 600);
+}
+
+#[test]
+fn test_validate_schedule() {
+    assert!(cliff_stream().validate_schedule().is_ok());
+    assert!(interval_stream().validate_schedule().is_ok());
+
+    let invalid_time_frame: ProgramError = PaymentError::InvalidTimeFrame.into();
+
+    let mut cliff_before_start = cliff_stream();
+    cliff_before_start.cliff_time = CLIFF_START - 1;
+    assert_eq!(cliff_before_start.validate_schedule(), Err(invalid_time_frame.clone()));
+
+    let mut end_before_cliff = cliff_stream();
+    end_before_cliff.end_time = CLIFF_TIME - 1;
+    assert_eq!(end_before_cliff.validate_schedule(), Err(invalid_time_frame.clone()));
+
+    let mut cliff_exceeds_total = cliff_stream();
+    cliff_exceeds_total.cliff_amount = CLIFF_TOTAL + 1;
+    assert_eq!(cliff_exceeds_total.validate_schedule(), Err(invalid_time_frame.clone()));
+
+    let mut zero_interval = interval_stream();
+    zero_interval.interval = 0;
+    assert_eq!(zero_interval.validate_schedule(), Err(invalid_time_frame));
+}
+
+// Release Curve Tests
+// -------------------
+
+/// Back-loaded table: 10% over the first 1_000 seconds, the rest over the next 1_000.
+fn breakpoint_stream() -> PaymentStream {
+    let mut payment_stream = cliff_stream();
+    payment_stream.release_curve = ReleaseCurve::Breakpoints(vec![
+        Breakpoint { timestamp: CLIFF_START + 1_000, cumulative_amount: 500 },
+        Breakpoint { timestamp: CLIFF_START + 2_000, cumulative_amount: CLIFF_TOTAL },
+    ]);
+    payment_stream
+}
+
+#[test]
+fn test_breakpoint_curve_interpolates_between_points() {
+    let payment_stream = breakpoint_stream();
+
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START + 500).unwrap(), 250);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START + 1_000).unwrap(), 500);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START + 1_500).unwrap(), 2_750);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_START + 2_000).unwrap(), CLIFF_TOTAL);
+    assert_eq!(payment_stream.calculate_vested_amount(UnixTimestamp::MAX).unwrap(), CLIFF_TOTAL);
+}
+
+#[test]
+fn test_breakpoint_curve_validation() {
+    assert!(breakpoint_stream().validate_schedule().is_ok());
+
+    let invalid_release_curve: ProgramError = PaymentError::InvalidReleaseCurve.into();
+    let invalid_tables = [
+        vec![],
+        // Timestamps must increase
+        vec![
+            Breakpoint { timestamp: CLIFF_START + 10, cumulative_amount: 500 },
+            Breakpoint { timestamp: CLIFF_START + 10, cumulative_amount: CLIFF_TOTAL },
+        ],
+        // Cumulative amounts can't decrease
+        vec![
+            Breakpoint { timestamp: CLIFF_START + 10, cumulative_amount: 500 },
+            Breakpoint { timestamp: CLIFF_START + 20, cumulative_amount: 400 },
+        ],
+        // The table must release the whole deposit
+        vec![Breakpoint { timestamp: CLIFF_START + 10, cumulative_amount: CLIFF_TOTAL - 1 }],
+        // Points can't precede the start
+        vec![Breakpoint { timestamp: CLIFF_START, cumulative_amount: CLIFF_TOTAL }],
+        vec![Breakpoint { timestamp: CLIFF_START + 1, cumulative_amount: CLIFF_TOTAL }; MAX_BREAKPOINTS + 1],
+    ];
+
+    for breakpoints in invalid_tables {
+        let mut payment_stream = cliff_stream();
+        payment_stream.release_curve = ReleaseCurve::Breakpoints(breakpoints);
+        assert_eq!(payment_stream.validate_schedule(), Err(invalid_release_curve.clone()));
+    }
+}
+
+#[test]
+fn test_exponential_decay_halves_unvested_each_half_life() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.release_curve = ReleaseCurve::ExponentialDecay { half_life: 1_000 };
+
+    // 4_000 vests after the cliff: half of the unvested part each 1_000 seconds
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME - 1).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME).unwrap(), CLIFF_AMOUNT);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 500).unwrap(), CLIFF_AMOUNT + 1_000);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 1_000).unwrap(), CLIFF_AMOUNT + 2_000);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 2_000).unwrap(), CLIFF_AMOUNT + 3_000);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 3_000).unwrap(), CLIFF_AMOUNT + 3_500);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END - 1).unwrap(), CLIFF_AMOUNT + 3_749);
+    // Whatever is left is released at the end
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END).unwrap(), CLIFF_TOTAL);
+}
+
+#[test]
+fn test_exponential_decay_requires_half_life() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.release_curve = ReleaseCurve::ExponentialDecay { half_life: 0 };
+
+    assert_eq!(payment_stream.validate_schedule(), Err(PaymentError::InvalidReleaseCurve.into()));
+}
+
+#[test]
+fn test_release_curves_handle_extreme_values() {
+    let mut payment_stream = interval_stream();
+    payment_stream.amount_per_interval = u64::MAX;
+    payment_stream.total_amount = u64::MAX;
+    payment_stream.interval = 1;
+    assert_eq!(payment_stream.calculate_vested_amount(UnixTimestamp::MAX).unwrap(), u64::MAX);
+
+    let mut payment_stream = cliff_stream();
+    payment_stream.total_amount = u64::MAX;
+    payment_stream.end_time = UnixTimestamp::MAX;
+    assert!(payment_stream.calculate_vested_amount(UnixTimestamp::MAX - 1).is_ok());
+}
+
+// Cancellation Tests
+// -------------------
+
+#[test]
+fn test_cancellation_policy_allows() {
+    assert!(CancellationPolicy::PayerOnly.allows(StreamParty::Payer));
+    assert!(!CancellationPolicy::PayerOnly.allows(StreamParty::Recipient));
+    assert!(!CancellationPolicy::RecipientOnly.allows(StreamParty::Payer));
+    assert!(CancellationPolicy::RecipientOnly.allows(StreamParty::Recipient));
+    assert!(CancellationPolicy::Both.allows(StreamParty::Payer));
+    assert!(CancellationPolicy::Both.allows(StreamParty::Recipient));
+    assert!(!CancellationPolicy::Neither.allows(StreamParty::Payer));
+    assert!(!CancellationPolicy::Neither.allows(StreamParty::Recipient));
+}
+
+#[test]
+fn test_transfer_flags_are_per_party() {
+    let mut payment_stream = interval_stream();
+    assert!(!payment_stream.is_transferable_by(StreamParty::Payer));
+    assert!(!payment_stream.is_transferable_by(StreamParty::Recipient));
+
+    payment_stream.transferable_by_recipient = true;
+    assert!(!payment_stream.is_transferable_by(StreamParty::Payer));
+    assert!(payment_stream.is_transferable_by(StreamParty::Recipient));
+
+    payment_stream.transferable_by_sender = true;
+    assert!(payment_stream.is_transferable_by(StreamParty::Payer));
+}
+
+#[test]
+fn test_termination_splits_vested_and_unvested() {
+    let payment_stream = interval_stream();
+    let halfway = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp;
+
+    // Without a penalty the recipient gets exactly what vested, whoever terminates
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Payer), Ok(10));
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Recipient), Ok(10));
+}
+
+#[test]
+fn test_termination_penalty_goes_to_counterparty() {
+    let mut payment_stream = interval_stream();
+    payment_stream.cancellation_penalty_bps = 1_000;
+    payment_stream.withdrawn_amount = TEST_AMOUNT_PER_INTERVAL;
+    let halfway = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp;
+
+    // 5 vested but unwithdrawn, 10 unvested: the payer forfeits 10% of the unvested
+    // funds to the recipient, the recipient 10% of the vested funds to the payer
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Payer), Ok(6));
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Recipient), Ok(4));
+
+    payment_stream.cancellation_penalty_bps = 10_000;
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Payer), Ok(15));
+    assert_eq!(payment_stream.termination_payout(halfway, StreamParty::Recipient), Ok(0));
+}
+
+// Top-up Tests
+// -------------
+
+#[test]
+fn test_top_up_extends_stepped_end_time_at_same_rate() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    payment_stream.top_up(2 * TEST_AMOUNT_PER_INTERVAL, start + interval).unwrap();
+    assert_eq!(payment_stream.total_amount, 6 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.end_time, start + 6 * interval);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 5 * interval).unwrap(), 5 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 6 * interval).unwrap(), 6 * TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[test]
+fn test_top_up_extends_linear_end_time_at_same_rate() {
+    let mut payment_stream = cliff_stream();
+
+    // One per second, so another 1_000 adds 1_000 seconds
+    payment_stream.top_up(1_000, CLIFF_TIME).unwrap();
+    assert_eq!(payment_stream.end_time, CLIFF_END + 1_000);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_TIME + 10).unwrap(), CLIFF_AMOUNT + 10);
+    assert_eq!(payment_stream.calculate_vested_amount(CLIFF_END + 1_000).unwrap(), CLIFF_TOTAL + 1_000);
+}
+
+#[test]
+fn test_top_up_revives_dry_stream_without_paying_dry_spell() {
+    let mut payment_stream = interval_stream();
+    payment_stream.withdrawn_amount = 4 * TEST_AMOUNT_PER_INTERVAL;
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    // Ran dry after four intervals and sat idle for another six
+    let now = start + 10 * interval;
+    payment_stream.top_up(2 * TEST_AMOUNT_PER_INTERVAL, now).unwrap();
+    assert_eq!(payment_stream.suspended_duration, 6 * TEST_INTERVAL);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now).unwrap(), 0);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now + interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(
+        payment_stream.calculate_max_withdrawable(now + 2 * interval).unwrap(),
+        2 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+#[test]
+fn test_top_up_completes_partly_funded_interval() {
+    let mut payment_stream = interval_stream();
+    payment_stream.total_amount = 3 * TEST_AMOUNT_PER_INTERVAL + 2;
+    let dry = TEST_START_TIME as UnixTimestamp + 4 * TEST_INTERVAL as UnixTimestamp;
+
+    payment_stream.top_up(TEST_AMOUNT_PER_INTERVAL, dry + 100).unwrap();
+    assert_eq!(payment_stream.calculate_vested_amount(dry + 100).unwrap(), 4 * TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[test]
+fn test_top_up_requires_a_rate() {
+    let mut payment_stream = cliff_stream();
+    payment_stream.release_curve = ReleaseCurve::ExponentialDecay { half_life: 100 };
+    assert_eq!(
+        payment_stream.top_up(1, CLIFF_TIME),
+        Err(PaymentError::InvalidReleaseCurve.into())
+    );
+
+    let mut payment_stream = cliff_stream();
+    payment_stream.cliff_amount = CLIFF_TOTAL;
+    assert_eq!(
+        payment_stream.top_up(1, CLIFF_TIME),
+        Err(PaymentError::InvalidReleaseCurve.into())
+    );
+}
+
+// Stream Update Tests
+// -------------------
+
+#[test]
+fn test_update_checkpoints_vested_funds() {
+    let mut payment_stream = interval_stream();
+    payment_stream.withdrawn_amount = TEST_AMOUNT_PER_INTERVAL;
+    let now = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp + 5;
+
+    // Two and a half intervals vested at the old rate, the half rounded up to 3,
+    // and one interval was withdrawn
+    payment_stream.update(TEST_INTERVAL, 2 * TEST_AMOUNT_PER_INTERVAL, now).unwrap();
+    assert_eq!(payment_stream.accrued_unwithdrawn, 8);
+    assert_eq!(payment_stream.total_amount, 7);
+    assert_eq!(payment_stream.withdrawn_amount, 0);
+    assert_eq!(payment_stream.start_time, now);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now).unwrap(), 8);
+
+    // The new terms run from the update on
+    assert_eq!(payment_stream.calculate_max_withdrawable(now + 9).unwrap(), 8);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now + 10).unwrap(), 15);
+    assert_eq!(payment_stream.end_time, now + 10);
+}
+
+#[test]
+fn test_shorter_interval_applies_only_from_update() {
+    let mut payment_stream = interval_stream();
+    let now = TEST_START_TIME as UnixTimestamp + 8;
+
+    // Most of an interval passed under the old terms; none of it counts toward the
+    // new, shorter intervals
+    payment_stream.update(2, 2, now).unwrap();
+    assert_eq!(payment_stream.calculate_max_withdrawable(now).unwrap(), 4);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now + 1).unwrap(), 4);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now + 2).unwrap(), 6);
+}
+
+#[test]
+fn test_repeated_updates_cannot_starve_recipient() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+
+    // Updating just before every interval completes settles the interval in
+    // progress, so the recipient never falls behind the untouched schedule
+    let untouched = payment_stream.clone();
+    for now in [start + 1, start + 9, start + 18, start + 27, start + 36] {
+        payment_stream.update(TEST_INTERVAL, TEST_AMOUNT_PER_INTERVAL, now).unwrap();
+        assert!(
+            payment_stream.calculate_max_withdrawable(now).unwrap()
+                >= untouched.calculate_max_withdrawable(now).unwrap()
+        );
+    }
+    assert_eq!(payment_stream.calculate_max_withdrawable(start + 40).unwrap(), 4 * TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[test]
+fn test_update_before_start_replaces_terms() {
+    let mut payment_stream = interval_stream();
+
+    payment_stream.update(2 * TEST_INTERVAL, TEST_AMOUNT_PER_INTERVAL, 0).unwrap();
+    assert_eq!(payment_stream.accrued_unwithdrawn, 0);
+    assert_eq!(payment_stream.start_time, TEST_START_TIME as UnixTimestamp);
+    assert_eq!(payment_stream.total_amount, 4 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.interval, 2 * TEST_INTERVAL);
+}
+
+#[test]
+fn test_update_rejects_invalid_terms() {
+    let mut payment_stream = interval_stream();
+    assert_eq!(payment_stream.update(0, TEST_AMOUNT_PER_INTERVAL, 0), Err(PaymentError::InvalidTimeFrame.into()));
+    assert_eq!(payment_stream.update(TEST_INTERVAL, 0, 0), Err(PaymentError::InvalidTimeFrame.into()));
+    assert_eq!(
+        cliff_stream().update(TEST_INTERVAL, TEST_AMOUNT_PER_INTERVAL, 0),
+        Err(PaymentError::InvalidReleaseCurve.into())
+    );
+}
+
+#[test]
+fn test_withdrawals_draw_on_accrued_funds_first() {
+    let mut payment_stream = interval_stream();
+    payment_stream.accrued_unwithdrawn = 3;
+
+    payment_stream.record_withdrawal(2).unwrap();
+    assert_eq!((payment_stream.accrued_unwithdrawn, payment_stream.withdrawn_amount), (1, 0));
+    payment_stream.record_withdrawal(4).unwrap();
+    assert_eq!((payment_stream.accrued_unwithdrawn, payment_stream.withdrawn_amount), (0, 3));
+}
+
+// Pause Tests
+// -----------
+
+#[test]
+fn test_paused_time_never_vests() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    payment_stream.pause(start + interval + 5);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 10 * interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+
+    // Half an interval had passed before the pause, so the next step lands half
+    // an interval after the resume
+    payment_stream.resume(start + 5 * interval);
+    assert_eq!(payment_stream.total_paused_duration, 4 * TEST_INTERVAL - 5);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 5 * interval + 4).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 5 * interval + 5).unwrap(),
+        2 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+#[test]
+fn test_vested_funds_stay_withdrawable_while_paused() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+    payment_stream.withdrawn_amount = TEST_AMOUNT_PER_INTERVAL;
+
+    payment_stream.pause(start + 2 * interval);
+    assert_eq!(payment_stream.calculate_max_withdrawable(start + 2 * interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.calculate_max_withdrawable(start + 9 * interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+}
+
+#[test]
+fn test_multiple_pause_cycles_accumulate() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    // Paused across the start, then twice more for two and three intervals
+    // while it runs. Only the paused time after the start counts.
+    payment_stream.pause(start - interval);
+    payment_stream.resume(start + interval);
+    payment_stream.pause(start + 2 * interval);
+    payment_stream.resume(start + 4 * interval);
+    payment_stream.pause(start + 5 * interval);
+    payment_stream.resume(start + 8 * interval);
+    assert_eq!(payment_stream.total_paused_duration, 6 * TEST_INTERVAL);
+
+    // Of the eight intervals since the start, two ran
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 8 * interval).unwrap(),
+        2 * TEST_AMOUNT_PER_INTERVAL
+    );
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 9 * interval).unwrap(),
+        3 * TEST_AMOUNT_PER_INTERVAL
+    );
+
+    // Pausing and resuming again without time passing changes nothing
+    payment_stream.pause(start + 9 * interval);
+    payment_stream.resume(start + 9 * interval);
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 9 * interval).unwrap(),
+        3 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+#[test]
+fn test_update_while_paused_starts_new_terms_at_resume() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    payment_stream.pause(start + interval);
+    payment_stream
+        .update(TEST_INTERVAL, 2 * TEST_AMOUNT_PER_INTERVAL, start + 3 * interval)
+        .unwrap();
+    assert_eq!(payment_stream.accrued_unwithdrawn, TEST_AMOUNT_PER_INTERVAL);
+
+    payment_stream.resume(start + 5 * interval);
+    assert_eq!(payment_stream.calculate_max_withdrawable(start + 5 * interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(
+        payment_stream.calculate_max_withdrawable(start + 6 * interval).unwrap(),
+        3 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+// Blackout Window Tests
+// ---------------------
+
+/// A window `from` intervals after the start of `interval_stream` lasting `length` intervals.
+fn blackout(from: u64, length: u64) -> BlackoutWindow {
+    let start = TEST_START_TIME as UnixTimestamp + (from * TEST_INTERVAL) as UnixTimestamp;
+    BlackoutWindow {
+        start,
+        end: start + (length * TEST_INTERVAL) as UnixTimestamp,
+    }
+}
+
+#[test]
+fn test_nothing_vests_during_blackout_window() {
+    let mut payment_stream = interval_stream();
+    payment_stream.set_blackout_windows(vec![blackout(1, 2)], 0).unwrap();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    assert_eq!(payment_stream.calculate_vested_amount(start + interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.calculate_vested_amount(start + 3 * interval).unwrap(), TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 4 * interval).unwrap(),
+        2 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+#[test]
+fn test_pause_inside_blackout_window_is_not_counted_twice() {
+    let mut payment_stream = interval_stream();
+    payment_stream.set_blackout_windows(vec![blackout(1, 2)], 0).unwrap();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    // Paused for two intervals, only one of them outside the window
+    payment_stream.pause(start + 2 * interval);
+    payment_stream.resume(start + 4 * interval);
+    assert_eq!(payment_stream.total_paused_duration, TEST_INTERVAL);
+    assert_eq!(
+        payment_stream.calculate_vested_amount(start + 5 * interval).unwrap(),
+        2 * TEST_AMOUNT_PER_INTERVAL
+    );
+}
+
+#[test]
+fn test_blackout_window_validation() {
+    let mut payment_stream = interval_stream();
+    let invalid = |windows: Vec<BlackoutWindow>| {
+        assert_eq!(
+            interval_stream().set_blackout_windows(windows, 0),
+            Err(PaymentError::InvalidBlackoutWindows.into())
+        );
+    };
+
+    invalid(vec![blackout(0, 0)]);
+    invalid(vec![blackout(1, 2), blackout(2, 2)]);
+    invalid(vec![blackout(3, 1), blackout(1, 1)]);
+    invalid((0..=MAX_BLACKOUT_WINDOWS as u64).map(|from| blackout(2 * from, 1)).collect());
+
+    // Windows may not begin before the stream does, or in the past
+    let mut window = blackout(0, 1);
+    window.start -= 1;
+    invalid(vec![window]);
+    assert_eq!(
+        payment_stream.set_blackout_windows(vec![blackout(1, 1)], TEST_START_TIME as UnixTimestamp + 11),
+        Err(PaymentError::InvalidBlackoutWindows.into())
+    );
+
+    assert!(payment_stream.set_blackout_windows(vec![blackout(0, 1), blackout(1, 1)], 0).is_ok());
+}
+
+#[test]
+fn test_updating_blackout_windows_keeps_begun_windows() {
+    let mut payment_stream = interval_stream();
+    payment_stream.set_blackout_windows(vec![blackout(1, 2), blackout(5, 1)], 0).unwrap();
+
+    // The first window is under way, so only the second is replaced
+    let now = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp;
+    payment_stream.set_blackout_windows(vec![blackout(6, 1)], now).unwrap();
+    assert_eq!(payment_stream.blackout_windows, vec![blackout(1, 2), blackout(6, 1)]);
+
+    payment_stream.set_blackout_windows(Vec::new(), now).unwrap();
+    assert_eq!(payment_stream.blackout_windows, vec![blackout(1, 2)]);
+}
+
+// Split Stream Tests
+// ------------------
+
+/// `interval_stream` split 50/30/20 between three recipients.
+fn split_stream() -> (PaymentStream, [Pubkey; 3]) {
+    let recipients = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut payment_stream = interval_stream();
+    payment_stream
+        .set_splits(vec![
+            SplitRecipient::new(recipients[0], 5_000),
+            SplitRecipient::new(recipients[1], 3_000),
+            SplitRecipient::new(recipients[2], 2_000),
+        ])
+        .unwrap();
+    (payment_stream, recipients)
+}
+
+#[test]
+fn test_split_validation() {
+    let recipient = Pubkey::new_unique();
+    let invalid = |splits: Vec<SplitRecipient>| {
+        assert_eq!(interval_stream().set_splits(splits), Err(PaymentError::InvalidSplits.into()));
+    };
+
+    invalid(vec![SplitRecipient::new(recipient, 9_999)]);
+    invalid(vec![SplitRecipient::new(recipient, 5_000), SplitRecipient::new(recipient, 5_000)]);
+    invalid(vec![
+        SplitRecipient::new(recipient, 10_000),
+        SplitRecipient::new(Pubkey::new_unique(), 0),
+    ]);
+    invalid(
+        (0..=MAX_SPLIT_RECIPIENTS)
+            .map(|_| SplitRecipient::new(Pubkey::new_unique(), 1_000))
+            .collect(),
+    );
+
+    let (payment_stream, recipients) = split_stream();
+    assert!(payment_stream.is_split());
+    assert!(recipients.iter().all(|recipient| payment_stream.is_recipient(recipient)));
+    assert!(!payment_stream.is_recipient(&recipient));
+}
+
+#[test]
+fn test_split_recipients_withdraw_their_own_share() {
+    let (mut payment_stream, [first, second, third]) = split_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    assert_eq!(payment_stream.withdrawable_by(&first, start + 2 * interval).unwrap(), 5);
+    assert_eq!(payment_stream.withdrawable_by(&second, start + 2 * interval).unwrap(), 3);
+    assert_eq!(payment_stream.withdrawable_by(&third, start + 2 * interval).unwrap(), 2);
+    assert_eq!(payment_stream.withdrawable_by(&Pubkey::new_unique(), start + 2 * interval).unwrap(), 0);
+
+    // One recipient withdrawing leaves the others' shares untouched
+    payment_stream.record_withdrawal_by(&first, 5).unwrap();
+    assert_eq!(payment_stream.withdrawable_by(&first, start + 2 * interval).unwrap(), 0);
+    assert_eq!(payment_stream.withdrawable_by(&second, start + 2 * interval).unwrap(), 3);
+
+    assert_eq!(payment_stream.withdrawable_by(&first, start + 4 * interval).unwrap(), 5);
+    assert_eq!(payment_stream.withdrawable_by(&second, start + 4 * interval).unwrap(), 6);
+    assert_eq!(payment_stream.withdrawable_by(&third, start + 4 * interval).unwrap(), 4);
+}
+
+#[test]
+fn test_termination_settles_every_split_recipient() {
+    let (mut payment_stream, [first, second, third]) = split_stream();
+    let now = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp;
+    payment_stream.record_withdrawal_by(&first, 5).unwrap();
+
+    let payout = payment_stream.calculate_max_withdrawable(now).unwrap();
+    assert_eq!(
+        payment_stream.settle_recipients(payout).unwrap(),
+        vec![(first, 0), (second, 3), (third, 2)]
+    );
+    assert_eq!(payment_stream.withdrawn_amount, 2 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(payment_stream.calculate_max_withdrawable(now).unwrap(), 0);
+}
+
+#[test]
+fn test_replaced_split_recipient_keeps_the_share() {
+    let (mut payment_stream, [first, second, _]) = split_stream();
+    let now = TEST_START_TIME as UnixTimestamp + 2 * TEST_INTERVAL as UnixTimestamp;
+    payment_stream.record_withdrawal_by(&first, 2).unwrap();
+
+    let new_recipient = Pubkey::new_unique();
+    assert_eq!(
+        payment_stream.replace_recipient(&first, &second),
+        Err(PaymentError::InvalidRecipient.into())
+    );
+    payment_stream.replace_recipient(&first, &new_recipient).unwrap();
+    assert!(!payment_stream.is_recipient(&first));
+    assert_eq!(payment_stream.withdrawable_by(&new_recipient, now).unwrap(), 3);
+}
+
+#[test]
+fn test_replaced_recipient_drops_the_payout_destination() {
+    let mut payment_stream = interval_stream();
+    let recipient = payment_stream.recipient;
+    payment_stream.payout_destination = Some(Pubkey::new_unique());
+
+    let new_recipient = Pubkey::new_unique();
+    payment_stream.replace_recipient(&recipient, &new_recipient).unwrap();
+    assert_eq!(payment_stream.recipient, new_recipient);
+    assert_eq!(payment_stream.payout_destination, None);
+}
+
+// Snapshot Tests
+// --------------
+
+#[test]
+fn test_snapshot_follows_stream_lifecycle() {
+    let mut payment_stream = interval_stream();
+    let start = TEST_START_TIME as UnixTimestamp;
+    let interval = TEST_INTERVAL as UnixTimestamp;
+
+    let snapshot = payment_stream.snapshot(start - 1).unwrap();
+    assert_eq!(snapshot.status, StreamStatus::Scheduled);
+    assert_eq!(snapshot.remaining_amount, 4 * TEST_AMOUNT_PER_INTERVAL);
+
+    payment_stream.record_withdrawal(TEST_AMOUNT_PER_INTERVAL).unwrap();
+    assert_eq!(
+        payment_stream.snapshot(start + 2 * interval).unwrap(),
+        StreamSnapshot {
+            timestamp: start + 2 * interval,
+            status: StreamStatus::Active,
+            vested_amount: 2 * TEST_AMOUNT_PER_INTERVAL,
+            withdrawable_amount: TEST_AMOUNT_PER_INTERVAL,
+            remaining_amount: 2 * TEST_AMOUNT_PER_INTERVAL,
+        }
+    );
+
+    payment_stream.pause(start + 2 * interval);
+    assert_eq!(payment_stream.snapshot(start + 3 * interval).unwrap().status, StreamStatus::Paused);
+    payment_stream.resume(start + 3 * interval);
+
+    let snapshot = payment_stream.snapshot(start + 10 * interval).unwrap();
+    assert_eq!(snapshot.status, StreamStatus::Completed);
+    assert_eq!(snapshot.withdrawable_amount, 3 * TEST_AMOUNT_PER_INTERVAL);
+    assert_eq!(snapshot.remaining_amount, 0);
+
+    // Nothing is left to vest or withdraw once the stream is settled
+    payment_stream.terminate();
+    let snapshot = payment_stream.snapshot(start + 10 * interval).unwrap();
+    assert_eq!(snapshot.status, StreamStatus::Terminated);
+    assert_eq!((snapshot.withdrawable_amount, snapshot.remaining_amount), (0, 0));
+}
+
+// Migration Tests
+// ---------------
+
+/// A lamport stream written under layout version 1, 1_000 into a 6_000 deposit.
+const V1_LAMPORT_STREAM: &[u8] = include_bytes!("fixtures/payment_stream_v1_lamport.bin");
+/// A paused SPL token stream written under layout version 1, split 70/30.
+const V1_SPLIT_TOKEN_STREAM: &[u8] = include_bytes!("fixtures/payment_stream_v1_split_token.bin");
+
+#[test]
+fn test_v1_lamport_stream_migrates() {
+    assert_eq!(V1_LAMPORT_STREAM[8], 1);
+    // Version 4 outgrew the reserved space, so older accounts are smaller
+    assert!(V1_LAMPORT_STREAM.len() < PaymentStream::LEN);
+    assert_eq!(PaymentStream::unpack(V1_LAMPORT_STREAM), Err(PaymentError::UnsupportedAccountVersion.into()));
+
+    let (payment_stream, version) = PaymentStream::unpack_any_version(V1_LAMPORT_STREAM).unwrap();
+    assert_eq!(version, 1);
+    assert_eq!(payment_stream.payer, Pubkey::new_from_array([1; 32]));
+    assert_eq!(payment_stream.recipient, Pubkey::new_from_array([2; 32]));
+    assert_eq!(payment_stream.delegate, Some(Pubkey::new_from_array([3; 32])));
+    assert_eq!((payment_stream.interval, payment_stream.amount_per_interval), (60, 100));
+    assert_eq!((payment_stream.total_amount, payment_stream.withdrawn_amount), (6_000, 1_000));
+    assert_eq!(payment_stream.cancellation_policy, CancellationPolicy::Both);
+    assert_eq!(payment_stream.cancellation_penalty_bps, 500);
+    assert!(payment_stream.allow_crank);
+    assert_eq!(
+        payment_stream.blackout_windows,
+        vec![BlackoutWindow { start: 1_700_001_000, end: 1_700_001_600 }]
+    );
+    assert_eq!((payment_stream.vault_bump, payment_stream.fees_paid), (254, 90));
+    assert_eq!(payment_stream.created_at, 0);
+    assert_eq!(payment_stream.payout_destination, None);
+
+    // Rewritten in the current layout, the stream reads back unchanged
+    let mut packed_data = vec![0u8; PaymentStream::LEN];
+    payment_stream.pack(&mut packed_data).unwrap();
+    assert_eq!(PaymentStream::unpack(&packed_data).unwrap(), payment_stream);
+    assert_eq!(
+        PaymentStream::unpack_any_version(&packed_data).unwrap(),
+        (payment_stream, PaymentStream::VERSION)
+    );
+}
+
+#[test]
+fn test_v1_split_token_stream_migrates() {
+    let (payment_stream, version) = PaymentStream::unpack_any_version(V1_SPLIT_TOKEN_STREAM).unwrap();
+    assert_eq!(version, 1);
+    assert_eq!(payment_stream.release_curve, ReleaseCurve::Linear);
+    assert!(payment_stream.is_spl_token);
+    assert_eq!(payment_stream.mint, Pubkey::new_from_array([7; 32]));
+    assert_eq!(payment_stream.transfer_fees_withheld, 12);
+    assert!(payment_stream.is_paused);
+    assert_eq!(payment_stream.paused_at, 1_700_043_200);
+
+    // Each recipient keeps its weight and withdrawal history, and is paced by the
+    // stream's last withdrawal until it withdraws again
+    let [first, second] = [Pubkey::new_from_array([5; 32]), Pubkey::new_from_array([6; 32])];
+    let last_withdraw_time = payment_stream.last_withdraw_time;
+    assert_eq!(
+        payment_stream.splits,
+        vec![
+            SplitRecipient { recipient: first, weight_bps: 7_000, withdrawn_amount: 175_000, last_withdraw_time },
+            SplitRecipient { recipient: second, weight_bps: 3_000, withdrawn_amount: 75_000, last_withdraw_time },
+        ]
+    );
+    assert_eq!(payment_stream.withdrawable_by(&first, 1_700_050_000).unwrap(), 175_000);
+    assert_eq!(payment_stream.withdrawable_by(&second, 1_700_050_000).unwrap(), 75_000);
+}
+
+#[test]
+fn test_v2_stream_reads_without_payout_destination() {
+    let mut payment_stream = interval_stream();
+    payment_stream.created_at = 1_700_000_000;
+
+    // Version 2 ended at `created_at`, leaving the rest of the account zeroed
+    let mut packed_data = vec![0u8; PaymentStream::LEN];
+    payment_stream.pack(&mut packed_data).unwrap();
+    packed_data[8] = 2;
+    assert_eq!(PaymentStream::unpack(&packed_data), Err(PaymentError::UnsupportedAccountVersion.into()));
+    assert_eq!(PaymentStream::unpack_any_version(&packed_data).unwrap(), (payment_stream, 2));
+}
+
+#[test]
+fn test_v3_split_stream_reads_without_split_withdraw_times() {
+    let (v1_stream, _) = PaymentStream::unpack_any_version(V1_SPLIT_TOKEN_STREAM).unwrap();
+
+    // Version 3 followed the version 1 fields with `created_at` and `payout_destination`,
+    // and its split recipients had no `last_withdraw_time`
+    let encoded_len = v1_stream.try_to_vec().unwrap().len() - v1_stream.splits.len() * 8 - 8 - 1;
+    let mut packed_data = V1_SPLIT_TOKEN_STREAM[..PaymentStream::HEADER_LEN + encoded_len].to_vec();
+    packed_data[8] = 3;
+    packed_data.extend_from_slice(&1_700_000_000i64.to_le_bytes());
+    packed_data.resize(V1_SPLIT_TOKEN_STREAM.len(), 0);
+
+    let expected_stream = PaymentStream { created_at: 1_700_000_000, ..v1_stream };
+    assert_eq!(PaymentStream::unpack(&packed_data), Err(PaymentError::UnsupportedAccountVersion.into()));
+    assert_eq!(PaymentStream::unpack_any_version(&packed_data).unwrap(), (expected_stream, 3));
+}
+
+#[test]
+fn test_unpack_any_version_rejects_unknown_versions() {
+    let mut packed_data = V1_LAMPORT_STREAM.to_vec();
+    packed_data[8] = PaymentStream::VERSION + 1;
+    assert_eq!(
+        PaymentStream::unpack_any_version(&packed_data),
+        Err(PaymentError::UnsupportedAccountVersion.into())
+    );
+
+    packed_data[8] = 0;
+    assert_eq!(
+        PaymentStream::unpack_any_version(&packed_data),
+        Err(PaymentError::UnsupportedAccountVersion.into())
+    );
+    assert_eq!(
+        PaymentStream::unpack_any_version(&[0u8; 16]),
+        Err(PaymentError::InvalidAccountType.into())
+    );
+}
+
 // Add more test cases as needed...
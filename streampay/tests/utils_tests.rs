@@ -0,0 +1,180 @@
+use solana_program::{account_info::AccountInfo, program_pack::Pack, pubkey::Pubkey};
+use spl_token_2022::{
+    extension::{
+        interest_bearing_mint::InterestBearingConfig,
+        non_transferable::NonTransferable,
+        transfer_fee::{TransferFeeAmount, TransferFeeConfig},
+        ExtensionType, StateWithExtensionsMut,
+    },
+    state::{Account, AccountState, Mint},
+};
+use crate::utils::{
+    calculate_fee, calculate_share, check_mint_extensions, check_vault_address, find_vault_address, unpack_mint,
+    withheld_transfer_fees,
+};
+use crate::error::PaymentError;
+
+/// Builds Token-2022 mint data with room for `extensions`, which `init` initializes.
+fn mint_data_with_extensions(
+    extensions: &[ExtensionType],
+    init: impl FnOnce(&mut StateWithExtensionsMut<Mint>),
+) -> Vec<u8> {
+    let len = ExtensionType::try_calculate_account_len::<Mint>(extensions).unwrap();
+    let mut data = vec![0u8; len];
+    let mut mint = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+    init(&mut mint);
+    mint.base = Mint {
+        decimals: 6,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    mint.pack_base();
+    mint.init_account_type().unwrap();
+    data
+}
+
+/// Builds Token-2022 token account data holding `withheld_amount` of withheld transfer fees.
+fn token_account_data_with_withheld_fees(withheld_amount: u64) -> Vec<u8> {
+    let len = ExtensionType::try_calculate_account_len::<Account>(&[ExtensionType::TransferFeeAmount]).unwrap();
+    let mut data = vec![0u8; len];
+    let mut account = StateWithExtensionsMut::<Account>::unpack_uninitialized(&mut data).unwrap();
+    account.init_extension::<TransferFeeAmount>(true).unwrap().withheld_amount = withheld_amount.into();
+    account.base = Account {
+        mint: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        state: AccountState::Initialized,
+        ..Account::default()
+    };
+    account.pack_base();
+    account.init_account_type().unwrap();
+    data
+}
+
+#[test]
+fn test_vault_address_roundtrip() {
+    let program_id = Pubkey::new_unique();
+    let payment_stream = Pubkey::new_unique();
+    let (vault, vault_bump) = find_vault_address(&payment_stream, &program_id);
+
+    assert!(check_vault_address(&vault, &payment_stream, vault_bump, &program_id).is_ok());
+}
+
+#[test]
+fn test_vault_address_rejects_other_stream() {
+    let program_id = Pubkey::new_unique();
+    let (vault, vault_bump) = find_vault_address(&Pubkey::new_unique(), &program_id);
+
+    let result = check_vault_address(&vault, &Pubkey::new_unique(), vault_bump, &program_id);
+    assert_eq!(result, Err(PaymentError::InvalidVaultAccount.into()));
+}
+
+#[test]
+fn test_legacy_mint_is_supported() {
+    let key = Pubkey::new_unique();
+    let owner = spl_token::id();
+    let mut lamports = 0;
+    let mut data = vec![0u8; Mint::LEN];
+    Mint::pack(Mint { decimals: 6, is_initialized: true, ..Mint::default() }, &mut data).unwrap();
+    let mint_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+    assert_eq!(unpack_mint(&mint_account).unwrap().decimals, 6);
+    assert!(check_mint_extensions(&mint_account).is_ok());
+}
+
+#[test]
+fn test_interest_bearing_mint_is_supported() {
+    let key = Pubkey::new_unique();
+    let owner = spl_token_2022::id();
+    let mut lamports = 0;
+    let mut data = mint_data_with_extensions(&[ExtensionType::InterestBearingConfig], |mint| {
+        mint.init_extension::<InterestBearingConfig>(true).unwrap();
+    });
+    let mint_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+    assert!(check_mint_extensions(&mint_account).is_ok());
+}
+
+#[test]
+fn test_transfer_fee_mint_is_supported() {
+    let key = Pubkey::new_unique();
+    let owner = spl_token_2022::id();
+    let mut lamports = 0;
+    let mut data = mint_data_with_extensions(&[ExtensionType::TransferFeeConfig], |mint| {
+        let transfer_fee_config = mint.init_extension::<TransferFeeConfig>(true).unwrap();
+        transfer_fee_config.newer_transfer_fee.transfer_fee_basis_points = 100.into();
+        transfer_fee_config.newer_transfer_fee.maximum_fee = u64::MAX.into();
+    });
+    let mint_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+    assert_eq!(unpack_mint(&mint_account).unwrap().decimals, 6);
+    assert!(check_mint_extensions(&mint_account).is_ok());
+}
+
+#[test]
+fn test_withheld_transfer_fees() {
+    let key = Pubkey::new_unique();
+    let owner = spl_token_2022::id();
+    let mut lamports = 0;
+    let mut data = token_account_data_with_withheld_fees(42);
+    let token_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+    assert_eq!(withheld_transfer_fees(&token_account), Ok(42));
+
+    // Accounts without the transfer-fee extension withhold nothing
+    let owner = spl_token::id();
+    let mut data = vec![0u8; Account::LEN];
+    Account::pack(
+        Account {
+            mint: Pubkey::new_unique(),
+            owner: Pubkey::new_unique(),
+            state: AccountState::Initialized,
+            ..Account::default()
+        },
+        &mut data,
+    )
+    .unwrap();
+    let token_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+    assert_eq!(withheld_transfer_fees(&token_account), Ok(0));
+}
+
+#[test]
+fn test_non_transferable_mint_is_rejected() {
+    let key = Pubkey::new_unique();
+    let owner = spl_token_2022::id();
+    let mut lamports = 0;
+    let mut data = mint_data_with_extensions(&[ExtensionType::NonTransferable], |mint| {
+        mint.init_extension::<NonTransferable>(true).unwrap();
+    });
+    let mint_account = AccountInfo::new(&key, false, false, &mut lamports, &mut data, &owner, false, 0);
+
+    assert_eq!(
+        check_mint_extensions(&mint_account),
+        Err(PaymentError::UnsupportedMintExtension.into())
+    );
+}
+
+#[test]
+fn test_fee_rounds_up_in_favor_of_treasury() {
+    assert_eq!(calculate_fee(0, 150), Ok(0));
+    assert_eq!(calculate_fee(1, 150), Ok(1));
+    assert_eq!(calculate_fee(10_000, 150), Ok(150));
+    assert_eq!(calculate_fee(10_001, 150), Ok(151));
+    assert_eq!(calculate_fee(1_000, 0), Ok(0));
+}
+
+#[test]
+fn test_share_rounds_down_in_favor_of_the_rest() {
+    assert_eq!(calculate_share(99, 100), 0);
+    assert_eq!(calculate_share(5_000, 100), 50);
+    assert_eq!(calculate_share(5_099, 100), 50);
+    assert_eq!(calculate_share(u64::MAX, 10_000), u64::MAX);
+}
+
+#[test]
+fn test_fee_on_max_amount_does_not_overflow() {
+    assert_eq!(calculate_fee(u64::MAX, 10_000), Ok(u64::MAX));
+    assert_eq!(calculate_fee(u64::MAX, 150), Ok(276_701_161_105_643_275));
+    assert_eq!(
+        calculate_fee(u64::MAX, u16::MAX),
+        Err(PaymentError::ArithmeticOverflow.into())
+    );
+}